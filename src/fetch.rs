@@ -0,0 +1,1332 @@
+//! Discovery and retrieval of OpenSearch descriptions over HTTP.
+
+use std::{io, sync::Arc, time::Duration};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use encoding_rs::{Encoding, UTF_8};
+use mime::Mime;
+use reqwest::{
+    header::{HeaderMap, ACCEPT, CONTENT_TYPE},
+    redirect, Client, Response, StatusCode,
+};
+use scraper::{Html, Selector};
+use url::Url;
+
+use crate::{Error, OpenSearchImage};
+
+/// The `rel` of a link to an OpenSearch description.
+pub const META_TAG_REL: &str = "search";
+/// The `type` of a link to an OpenSearch description.
+pub const META_TAG_TYPE: &str = "application/opensearchdescription+xml";
+
+/// Sites filtering out bots tend to let through clients that look like a browser.
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "Mozilla/5.0 (compatible; ",
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    ")"
+);
+const ACCEPT_HTML: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+const ACCEPT_DESCRIPTION: &str =
+    "application/opensearchdescription+xml,application/xml;q=0.9,text/xml;q=0.9,*/*;q=0.8";
+const ACCEPT_IMAGE: &str = "image/*,*/*;q=0.8";
+/// How far into a page browsers look for a `<meta>` charset declaration.
+const META_CHARSET_PRESCAN_LENGTH: usize = 1024;
+/// How far into a typeless icon to look for an `<svg>` element.
+const SVG_SNIFF_LENGTH: usize = 1024;
+const META_CHARSET_ATTRIBUTE: &[u8] = b"charset";
+const HTML_DOCTYPE: &[u8] = b"<!doctype html";
+const HTML_ELEMENT: &[u8] = b"<html";
+const XML_ENCODING_ATTRIBUTE: &[u8] = b"encoding";
+/// The most visible text a page's body can have and still pass for a JavaScript app's shell.
+const SCRIPT_SHELL_TEXT_LENGTH: usize = 200;
+/// Elements whose text isn't shown as part of the page.
+const INVISIBLE_ELEMENTS: &[&str] = &["script", "noscript", "style", "template"];
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_RETRIES: u32 = 2;
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Called with the url being left and the url being redirected to for every redirect followed.
+pub type RedirectHook = Arc<dyn Fn(&Url, &Url) + Send + Sync>;
+
+/// How a [`Fetcher`] makes its requests.
+#[derive(Clone)]
+pub struct FetcherConfig {
+    /// How long to wait for each request before giving up.
+    pub timeout: Duration,
+    /// How many times to retry a request that timed out or hit a server error.
+    pub retries: u32,
+    /// How many redirects a request may follow before it fails.
+    pub max_redirects: usize,
+    pub user_agent: String,
+    /// Sent with every request, such as the credentials or cookies a site wants.
+    pub headers: HeaderMap,
+    /// Told about each redirect as it's followed, such as to log it.
+    pub on_redirect: Option<RedirectHook>,
+}
+
+impl Default for FetcherConfig {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: HeaderMap::new(),
+            on_redirect: None,
+        }
+    }
+}
+
+/// The http client shared by every request so connections and TLS sessions are reused.
+#[derive(Debug, Clone)]
+pub struct Fetcher {
+    client: Client,
+    retries: u32,
+    max_redirects: usize,
+}
+
+impl Fetcher {
+    /// Creates a fetcher whose requests give up after `timeout`, retrying timeouts and server
+    /// errors up to `retries` times.
+    pub fn new(timeout: Duration, retries: u32) -> Result<Self, Error> {
+        Self::with_config(FetcherConfig {
+            timeout,
+            retries,
+            ..FetcherConfig::default()
+        })
+    }
+
+    /// Creates a fetcher configured beyond the timeout and retries.
+    pub fn with_config(config: FetcherConfig) -> Result<Self, Error> {
+        let max_redirects = config.max_redirects;
+        let on_redirect = config.on_redirect;
+        let redirect_policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                return attempt.error(format!("more than {} redirects", max_redirects));
+            }
+
+            if let (Some(hook), Some(from)) = (&on_redirect, attempt.previous().last()) {
+                hook(from, attempt.url());
+            }
+
+            attempt.follow()
+        });
+
+        let client = Client::builder()
+            .user_agent(config.user_agent)
+            .default_headers(config.headers)
+            .timeout(config.timeout)
+            .redirect(redirect_policy)
+            .build()
+            .map_err(Error::BuildClient)?;
+
+        Ok(Self {
+            client,
+            retries: config.retries,
+            max_redirects,
+        })
+    }
+
+    /// Replaces the generic error reqwest gives for a request that redirected too often.
+    fn redirect_error(&self, url: &Url, error: &reqwest::Error) -> Option<Error> {
+        error.is_redirect().then(|| Error::TooManyRedirects {
+            url: url.clone(),
+            max: self.max_redirects,
+        })
+    }
+
+    /// Requests `url`, telling the server which content types are `accept`able. An error status
+    /// is left for the caller to report once any retries are spent.
+    async fn get(&self, url: &Url, accept: &str) -> reqwest::Result<Response> {
+        let mut attempt = 0;
+
+        loop {
+            let result = self
+                .client
+                .get(url.clone())
+                .header(ACCEPT, accept)
+                .send()
+                .await;
+            let retryable = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(error) => error.is_timeout(),
+            };
+
+            if !retryable {
+                return result;
+            }
+
+            if attempt >= self.retries {
+                return result;
+            }
+
+            tokio::time::sleep(retry_backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// How long to wait before the retry following `attempt`, doubling each time.
+fn retry_backoff(attempt: u32) -> Duration {
+    RETRY_BACKOFF * 2u32.saturating_pow(attempt)
+}
+
+/// Requests the results of a query, such as an engine's example query, for the status it gets.
+pub async fn get_query_status(fetcher: &Fetcher, url: Url) -> Result<StatusCode, Error> {
+    fetcher
+        .get(&url, ACCEPT_HTML)
+        .await
+        .map(|response| response.status())
+        .map_err(|source| {
+            fetcher
+                .redirect_error(&url, &source)
+                .unwrap_or(Error::FetchQuery { url, source })
+        })
+}
+
+/// Reads a `file://` url from disk, for pages and descriptions saved locally.
+fn read_file_url(url: &Url) -> Result<Vec<u8>, Error> {
+    let read_error = |source| Error::ReadFileUrl {
+        url: url.clone(),
+        source,
+    };
+
+    let path = url.to_file_path().map_err(|()| {
+        read_error(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a local file path",
+        ))
+    })?;
+
+    std::fs::read(path).map_err(read_error)
+}
+
+/// Fetches a webpage, or reads it from disk for a `file://` url, along with the url it ended up
+/// at after any redirects, which its relative links are relative to.
+pub async fn get_webpage_raw(fetcher: &Fetcher, url: Url) -> Result<(Url, String), Error> {
+    if url.scheme() == "file" {
+        let webpage_raw = decode_html(&read_file_url(&url)?, None);
+        return Ok((url, webpage_raw));
+    }
+
+    let fetch_error = |source| Error::FetchWebpage {
+        url: url.clone(),
+        source,
+    };
+
+    let response = fetcher.get(&url, ACCEPT_HTML).await.map_err(|source| {
+        fetcher
+            .redirect_error(&url, &source)
+            .unwrap_or_else(|| fetch_error(source))
+    })?;
+
+    if !response.status().is_success() {
+        return Err(Error::WebpageStatus {
+            url,
+            status: response.status(),
+        });
+    }
+
+    let final_url = response.url().clone();
+    let charset = response_charset(&response);
+    let bytes = response.bytes().await.map_err(fetch_error)?;
+
+    Ok((final_url, decode_html(&bytes, charset.as_deref())))
+}
+
+/// Decodes a webpage by the served `charset`, falling back to one its `<meta>` tags declare,
+/// then to UTF-8. A `<meta>` tag that could be read as ASCII can't be right about UTF-16, so it
+/// means UTF-8 there, as it does to browsers.
+fn decode_html(bytes: &[u8], charset: Option<&str>) -> String {
+    let encoding = charset
+        .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+        .or_else(|| {
+            html_meta_charset(bytes)
+                .and_then(Encoding::for_label)
+                .map(Encoding::output_encoding)
+        })
+        .unwrap_or(UTF_8);
+
+    encoding.decode_with_bom_removal(bytes).0.into_owned()
+}
+
+/// The charset named by a `<meta charset>` or `<meta http-equiv="Content-Type">` tag near the
+/// start of the page, where browsers look for it.
+fn html_meta_charset(bytes: &[u8]) -> Option<&[u8]> {
+    let prescan = &bytes[..bytes.len().min(META_CHARSET_PRESCAN_LENGTH)];
+    let lowercase = prescan.to_ascii_lowercase();
+    let mut offset = 0;
+
+    while let Some(start) = find_bytes(&lowercase[offset..], b"<meta") {
+        let tag_start = offset + start;
+        let tag_end = find_bytes(&lowercase[tag_start..], b">")
+            .map_or(lowercase.len(), |end| tag_start + end);
+        offset = tag_end;
+
+        let Some(attribute) = find_bytes(&lowercase[tag_start..tag_end], META_CHARSET_ATTRIBUTE)
+        else {
+            continue;
+        };
+
+        let value_start = tag_start + attribute + META_CHARSET_ATTRIBUTE.len();
+        let Some(value) = prescan[value_start..tag_end]
+            .trim_ascii_start()
+            .strip_prefix(b"=")
+        else {
+            continue;
+        };
+        let value = value.trim_ascii_start();
+        let value = value
+            .strip_prefix(b"\"")
+            .or_else(|| value.strip_prefix(b"'"))
+            .unwrap_or(value);
+        let value_end = value
+            .iter()
+            .position(|character| b"\"'; \t\r\n/>".contains(character))
+            .unwrap_or(value.len());
+
+        if value_end > 0 {
+            return Some(&value[..value_end]);
+        }
+    }
+
+    None
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+pub fn parse_webpage(webpage_raw: impl AsRef<str>) -> Html {
+    Html::parse_document(webpage_raw.as_ref())
+}
+
+/// Whether the page looks like the shell of a JavaScript app: next to no visible text, but
+/// scripts that render the rest, and with it perhaps the search link, in the browser.
+pub fn looks_script_rendered(document: &Html) -> bool {
+    let script_selector = Selector::parse("script").expect("Invalid script selector");
+    let body_selector = Selector::parse("body").expect("Invalid body selector");
+
+    if document.select(&script_selector).next().is_none() {
+        return false;
+    }
+
+    let visible_text_length = document
+        .select(&body_selector)
+        .flat_map(|body| body.descendants())
+        .filter(|node| {
+            node.parent()
+                .and_then(|parent| parent.value().as_element().map(|element| element.name()))
+                .is_none_or(|name| !INVISIBLE_ELEMENTS.contains(&name))
+        })
+        .filter_map(|node| node.value().as_text())
+        .map(|text| text.split_whitespace().map(str::len).sum::<usize>())
+        .sum::<usize>();
+
+    visible_text_length < SCRIPT_SHELL_TEXT_LENGTH
+}
+
+/// The `rel` and `type` a link to an OpenSearch description is recognized by, for pages that
+/// use nonstandard ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkMatcher {
+    pub rel: String,
+    pub link_type: String,
+}
+
+impl Default for LinkMatcher {
+    fn default() -> Self {
+        Self {
+            rel: META_TAG_REL.to_string(),
+            link_type: META_TAG_TYPE.to_string(),
+        }
+    }
+}
+
+impl LinkMatcher {
+    /// Whether a link's `rel` contains the search relation, ignoring case and whitespace.
+    fn is_search_rel(&self, rel: &str) -> bool {
+        rel.split_ascii_whitespace()
+            .any(|rel| rel.eq_ignore_ascii_case(&self.rel))
+    }
+
+    /// Whether a link's `type` is the OpenSearch description type, ignoring case, whitespace,
+    /// and parameters such as `charset`.
+    fn is_opensearch_type(&self, link_type: &str) -> bool {
+        link_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .eq_ignore_ascii_case(self.link_type.trim())
+    }
+}
+
+pub fn select_opensearch_url(document: &Html, current_url: &Url) -> Result<Url, Error> {
+    select_opensearch_urls(document, current_url).map(|mut urls| urls.swap_remove(0))
+}
+
+/// Every OpenSearch description linked from the page, in document order.
+pub fn select_opensearch_urls(document: &Html, current_url: &Url) -> Result<Vec<Url>, Error> {
+    select_opensearch_urls_with(document, current_url, &LinkMatcher::default())
+}
+
+/// Like [`select_opensearch_urls`], but recognizing links by the `rel` and `type` of `matcher`.
+pub fn select_opensearch_urls_with(
+    document: &Html,
+    current_url: &Url,
+    matcher: &LinkMatcher,
+) -> Result<Vec<Url>, Error> {
+    let urls = select_search_links(document, current_url, matcher, |link_type| {
+        link_type.is_some_and(|link_type| matcher.is_opensearch_type(link_type))
+    })?;
+
+    if urls.is_empty() {
+        Err(Error::NoDescription(current_url.clone()))
+    } else {
+        Ok(urls)
+    }
+}
+
+/// Like [`select_opensearch_urls`], but followed by any `rel="search"` links that leave out the
+/// type or give a generic xml one. Those may not lead to a description at all, so what they
+/// serve should be checked before it's used.
+pub fn select_opensearch_urls_lenient(
+    document: &Html,
+    current_url: &Url,
+) -> Result<Vec<Url>, Error> {
+    select_opensearch_urls_lenient_with(document, current_url, &LinkMatcher::default())
+}
+
+/// Like [`select_opensearch_urls_lenient`], but recognizing links by the `rel` and `type` of
+/// `matcher`.
+pub fn select_opensearch_urls_lenient_with(
+    document: &Html,
+    current_url: &Url,
+    matcher: &LinkMatcher,
+) -> Result<Vec<Url>, Error> {
+    let mut urls = select_search_links(document, current_url, matcher, |link_type| {
+        link_type.is_some_and(|link_type| matcher.is_opensearch_type(link_type))
+    })?;
+    let untyped_urls = select_search_links(document, current_url, matcher, |link_type| {
+        link_type.is_none_or(is_generic_xml_type)
+    })?;
+
+    for url in untyped_urls {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    if urls.is_empty() {
+        Err(Error::NoDescription(current_url.clone()))
+    } else {
+        Ok(urls)
+    }
+}
+
+/// Whether a link's `type` is a plain xml type that an OpenSearch description may be served as.
+fn is_generic_xml_type(link_type: &str) -> bool {
+    let essence = link_type.split(';').next().unwrap_or_default().trim();
+
+    essence.eq_ignore_ascii_case("application/xml") || essence.eq_ignore_ascii_case("text/xml")
+}
+
+/// The targets of the page's search links whose `type`, if any, is accepted.
+fn select_search_links(
+    document: &Html,
+    current_url: &Url,
+    matcher: &LinkMatcher,
+    accepts_type: impl Fn(Option<&str>) -> bool,
+) -> Result<Vec<Url>, Error> {
+    let link_selector = Selector::parse("link[rel]").expect("Invalid link selector");
+
+    document
+        .select(&link_selector)
+        .map(|link| link.value())
+        .filter(|link| {
+            link.attr("rel")
+                .is_some_and(|rel| matcher.is_search_rel(rel))
+                && accepts_type(link.attr("type"))
+        })
+        .map(|link| {
+            let url_raw = link
+                .attr("href")
+                .ok_or_else(|| Error::MissingDescriptionHref(current_url.clone()))?;
+
+            current_url
+                .join(url_raw)
+                .map_err(|source| Error::InvalidDescriptionUrl {
+                    href: url_raw.to_string(),
+                    source,
+                })
+        })
+        .collect()
+}
+
+pub async fn get_opensearch_raw(fetcher: &Fetcher, url: Url) -> Result<String, Error> {
+    // A saved page's relative link resolves to a file beside it.
+    if url.scheme() == "file" {
+        return Ok(decode_xml(&read_file_url(&url)?, None));
+    }
+
+    let fetch_error = |source| Error::FetchDescription {
+        url: url.clone(),
+        source,
+    };
+
+    let response = fetcher
+        .get(&url, ACCEPT_DESCRIPTION)
+        .await
+        .map_err(|source| {
+            fetcher
+                .redirect_error(&url, &source)
+                .unwrap_or_else(|| fetch_error(source))
+        })?;
+    let status = response.status();
+
+    if status == StatusCode::NOT_FOUND {
+        return Err(Error::DescriptionNotFound(url));
+    }
+
+    if !status.is_success() {
+        return Err(Error::DescriptionStatus { url, status });
+    }
+
+    let charset = response_charset(&response);
+    let bytes = response.bytes().await.map_err(fetch_error)?;
+    let opensearch_raw = decode_xml(&bytes, charset.as_deref());
+
+    if opensearch_raw.trim().is_empty() {
+        return Err(Error::EmptyDescription { url, status });
+    }
+
+    if is_html_document(&opensearch_raw) {
+        return Err(Error::HtmlDescription { url, status });
+    }
+
+    Ok(opensearch_raw)
+}
+
+/// Whether a response is a webpage, such as an error page served in place of xml.
+fn is_html_document(raw: &str) -> bool {
+    let start = raw.trim_start().as_bytes();
+
+    [HTML_DOCTYPE, HTML_ELEMENT].iter().any(|prefix| {
+        start
+            .get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    })
+}
+
+/// The `charset` parameter of the response's content type, if it has one.
+fn response_charset(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get(CONTENT_TYPE)?
+        .to_str()
+        .ok()?
+        .parse::<Mime>()
+        .ok()?
+        .get_param(mime::CHARSET)
+        .map(|charset| charset.to_string())
+}
+
+/// Decodes an xml document by the served `charset`, falling back to the encoding its xml
+/// declaration names, then to UTF-8.
+fn decode_xml(bytes: &[u8], charset: Option<&str>) -> String {
+    let encoding = charset
+        .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+        .or_else(|| xml_declared_encoding(bytes).and_then(Encoding::for_label))
+        .unwrap_or(UTF_8);
+
+    encoding.decode_with_bom_removal(bytes).0.into_owned()
+}
+
+/// The `encoding` named by a leading `<?xml ... ?>` declaration.
+fn xml_declared_encoding(bytes: &[u8]) -> Option<&[u8]> {
+    let declaration = bytes.strip_prefix(b"<?xml")?;
+    let declaration = &declaration[..declaration.windows(2).position(|end| end == b"?>")?];
+    let start = declaration
+        .windows(XML_ENCODING_ATTRIBUTE.len())
+        .position(|attribute| attribute == XML_ENCODING_ATTRIBUTE)?
+        + XML_ENCODING_ATTRIBUTE.len();
+    let value = declaration[start..].trim_ascii_start().strip_prefix(b"=")?;
+    let value = value.trim_ascii_start();
+    let quote = *value
+        .first()
+        .filter(|quote| matches!(quote, b'"' | b'\''))?;
+    let value = &value[1..];
+
+    Some(&value[..value.iter().position(|&character| character == quote)?])
+}
+
+/// Downloads an icon and encodes it as a base64 `data:` uri, preferring the served content type
+/// over the declared one, and either over what the bytes look like. An icon over `max_size`
+/// bytes is an error.
+pub async fn get_icon_data_uri(
+    fetcher: &Fetcher,
+    image: &OpenSearchImage,
+    max_size: Option<u64>,
+) -> Result<String, Error> {
+    let fetch_error = |source| Error::FetchIcon {
+        url: image.url.clone(),
+        source,
+    };
+
+    let response = fetcher
+        .get(&image.url, ACCEPT_IMAGE)
+        .await
+        .and_then(Response::error_for_status)
+        .map_err(fetch_error)?;
+
+    let served_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.parse::<Mime>().ok())
+        .filter(|content_type| content_type.type_() == mime::IMAGE);
+
+    let too_large = |size| {
+        max_size
+            .filter(|&max| size > max)
+            .map(|max| Error::IconTooLarge {
+                url: image.url.clone(),
+                size,
+                max,
+            })
+    };
+
+    // Skip downloading an icon the server already says is too large.
+    if let Some(error) = response.content_length().and_then(too_large) {
+        return Err(error);
+    }
+
+    let bytes = response.bytes().await.map_err(fetch_error)?;
+
+    if let Some(error) = too_large(bytes.len() as u64) {
+        return Err(error);
+    }
+
+    let image_type = served_type
+        .or_else(|| image.image_type.clone())
+        .or_else(|| sniff_image_type(&bytes))
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+    Ok(icon_data_uri(&image_type, &bytes))
+}
+
+/// Recognizes the common icon formats by their leading bytes.
+fn sniff_image_type(bytes: &[u8]) -> Option<Mime> {
+    let image_type = if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        mime::IMAGE_PNG
+    } else if bytes.starts_with(&[0, 0, 1, 0]) {
+        "image/x-icon".parse().ok()?
+    } else if bytes.starts_with(b"GIF8") {
+        mime::IMAGE_GIF
+    } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        mime::IMAGE_JPEG
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        "image/webp".parse().ok()?
+    } else if is_svg(bytes) {
+        mime::IMAGE_SVG
+    } else {
+        return None;
+    };
+
+    Some(image_type)
+}
+
+fn is_svg(bytes: &[u8]) -> bool {
+    let start = &bytes[..bytes.len().min(SVG_SNIFF_LENGTH)];
+    find_bytes(&start.to_ascii_lowercase(), b"<svg").is_some()
+}
+
+fn icon_data_uri(image_type: &Mime, bytes: &[u8]) -> String {
+    format!(
+        "data:{};base64,{}",
+        image_type.essence_str(),
+        BASE64_STANDARD.encode(bytes)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fetcher_builds() {
+        assert!(Fetcher::new(DEFAULT_TIMEOUT, DEFAULT_RETRIES).is_ok());
+    }
+
+    #[tokio::test]
+    async fn redirects_reported_and_capped() {
+        use std::sync::Mutex;
+
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let redirect = |to: &str| ResponseTemplate::new(302).insert_header("location", to);
+        Mock::given(path("/a"))
+            .respond_with(redirect("/b"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/b"))
+            .respond_with(redirect("/opensearch.xml"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/opensearch.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<OpenSearchDescription />"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/loop"))
+            .respond_with(redirect("/loop"))
+            .mount(&server)
+            .await;
+
+        let base = Url::parse(&server.uri()).unwrap();
+        let hops = Arc::new(Mutex::new(Vec::new()));
+        let log = hops.clone();
+        let fetcher = Fetcher::with_config(FetcherConfig {
+            retries: 0,
+            max_redirects: 2,
+            on_redirect: Some(Arc::new(move |from: &Url, to: &Url| {
+                log.lock()
+                    .unwrap()
+                    .push((from.path().to_string(), to.path().to_string()))
+            })),
+            ..FetcherConfig::default()
+        })
+        .unwrap();
+
+        let raw = get_opensearch_raw(&fetcher, base.join("/a").unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(raw, "<OpenSearchDescription />");
+        assert_eq!(
+            *hops.lock().unwrap(),
+            [
+                ("/a".to_string(), "/b".to_string()),
+                ("/b".to_string(), "/opensearch.xml".to_string())
+            ]
+        );
+
+        let error = get_webpage_raw(&fetcher, base.join("/loop").unwrap())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::TooManyRedirects { max: 2, .. }));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{} redirected more than 2 times",
+                base.join("/loop").unwrap()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn gzip_descriptor_decoded() {
+        use std::io::Write;
+
+        use flate2::{write::GzEncoder, Compression};
+        use wiremock::{
+            matchers::{header_regex, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        const DESCRIPTOR: &str =
+            "<OpenSearchDescription><ShortName>Test</ShortName></OpenSearchDescription>";
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(DESCRIPTOR.as_bytes()).unwrap();
+
+        let server = MockServer::start().await;
+        Mock::given(path("/opensearch.xml"))
+            .and(header_regex("accept-encoding", "gzip"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_bytes(encoder.finish().unwrap()),
+            )
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let url = Url::parse(&server.uri())
+            .unwrap()
+            .join("/opensearch.xml")
+            .unwrap();
+
+        assert_eq!(get_opensearch_raw(&fetcher, url).await.unwrap(), DESCRIPTOR);
+    }
+
+    #[tokio::test]
+    async fn served_webpage_meta_tags() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/page"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<html><head>
+                    <link rel="search" type="application/opensearchdescription+xml" href="opensearch.xml">
+                    <link rel="search" type="application/opensearchdescription+xml" href="https://other.example.com/os.xml">
+                </head></html>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let url = Url::parse(&server.uri()).unwrap().join("/page").unwrap();
+        let webpage = parse_webpage(get_webpage_raw(&fetcher, url.clone()).await.unwrap().1);
+
+        assert_eq!(
+            select_opensearch_urls(&webpage, &url).unwrap(),
+            [
+                url.join("/opensearch.xml").unwrap(),
+                Url::parse("https://other.example.com/os.xml").unwrap(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn error_statuses() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/missing"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("<html>Not found</html>"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/broken"))
+            .respond_with(ResponseTemplate::new(500).set_body_string("<html>Oops</html>"))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let base = Url::parse(&server.uri()).unwrap();
+        let missing = base.join("/missing").unwrap();
+        let broken = base.join("/broken").unwrap();
+
+        let error = get_opensearch_raw(&fetcher, missing.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("descriptor not found (404) at {}", missing)
+        );
+
+        let error = get_opensearch_raw(&fetcher, broken.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::DescriptionStatus { status, .. } if status == StatusCode::INTERNAL_SERVER_ERROR
+        ));
+
+        for url in [missing, broken] {
+            let error = get_webpage_raw(&fetcher, url.clone()).await.unwrap_err();
+            assert!(matches!(error, Error::WebpageStatus { .. }));
+            assert!(error.to_string().contains(url.as_str()));
+        }
+    }
+
+    #[tokio::test]
+    async fn local_webpage() {
+        const DESCRIPTOR: &str =
+            "<OpenSearchDescription><ShortName>Test</ShortName></OpenSearchDescription>";
+
+        let dir = std::env::temp_dir().join(format!("opensearch-local-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("page.html"),
+            r#"<html><head>
+                <link rel="search" type="application/opensearchdescription+xml" href="opensearch.xml">
+            </head></html>"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("opensearch.xml"), DESCRIPTOR).unwrap();
+
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let url = Url::from_file_path(dir.join("page.html")).unwrap();
+        let webpage = parse_webpage(get_webpage_raw(&fetcher, url).await.unwrap().1);
+        let urls = select_opensearch_urls(
+            &webpage,
+            &Url::from_file_path(dir.join("page.html")).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            urls,
+            [Url::from_file_path(dir.join("opensearch.xml")).unwrap()]
+        );
+        assert_eq!(
+            get_opensearch_raw(&fetcher, urls[0].clone()).await.unwrap(),
+            DESCRIPTOR
+        );
+
+        let missing = Url::from_file_path(dir.join("missing.html")).unwrap();
+        assert!(matches!(
+            get_webpage_raw(&fetcher, missing).await,
+            Err(Error::ReadFileUrl { .. })
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn browser_like_headers() {
+        use wiremock::{
+            matchers::{header, header_regex, path},
+            Mock, MockServer, Request, ResponseTemplate,
+        };
+
+        // The header matchers split values on commas, so compare the whole Accept header here.
+        let accepts_html = |request: &Request| {
+            request
+                .headers
+                .get("accept")
+                .is_some_and(|accept| accept == ACCEPT_HTML)
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(path("/page"))
+            .and(accepts_html)
+            .and(header("user-agent", DEFAULT_USER_AGENT))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html></html>"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/custom"))
+            .and(header_regex("accept", "^text/html"))
+            .and(header("user-agent", "Custom/1.0"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html></html>"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let url = Url::parse(&server.uri()).unwrap();
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        get_webpage_raw(&fetcher, url.join("/page").unwrap())
+            .await
+            .unwrap();
+
+        let fetcher = Fetcher::with_config(FetcherConfig {
+            retries: 0,
+            user_agent: "Custom/1.0".to_string(),
+            ..FetcherConfig::default()
+        })
+        .unwrap();
+        get_webpage_raw(&fetcher, url.join("/custom").unwrap())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn script_rendered_pages() {
+        assert!(looks_script_rendered(&parse_webpage(
+            r#"<!doctype html><html><head>
+                <script type="module" src="/assets/index-4f2a.js"></script>
+            </head><body>
+                <div id="root"></div>
+                <noscript>You need to enable JavaScript to run this app.</noscript>
+            </body></html>"#,
+        )));
+
+        // Server rendered pages have their content, scripts or not.
+        let article = "Lorem ipsum dolor sit amet. ".repeat(20);
+        assert!(!looks_script_rendered(&parse_webpage(format!(
+            r#"<html><head><script src="/analytics.js"></script></head><body>
+                <main><p>{}</p></main>
+            </body></html>"#,
+            article
+        ))));
+        // An empty page without scripts has nothing to render a link later.
+        assert!(!looks_script_rendered(&parse_webpage("<html></html>")));
+    }
+
+    #[test]
+    fn lenient_discovery() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let webpage = parse_webpage(
+            r#"<html><head>
+                <link rel="search" href="/typeless.xml">
+                <link rel="search" type="text/xml" href="/xml.xml">
+                <link rel="search" type="text/html" href="/search">
+                <link rel="search" type="application/opensearchdescription+xml" href="/typed.xml">
+            </head></html>"#,
+        );
+
+        assert_eq!(
+            select_opensearch_urls(&webpage, &url).unwrap(),
+            [url.join("/typed.xml").unwrap()]
+        );
+        assert_eq!(
+            select_opensearch_urls_lenient(&webpage, &url).unwrap(),
+            [
+                url.join("/typed.xml").unwrap(),
+                url.join("/typeless.xml").unwrap(),
+                url.join("/xml.xml").unwrap(),
+            ]
+        );
+
+        let webpage = parse_webpage(r#"<link rel="search" type="text/html" href="/search">"#);
+        assert!(matches!(
+            select_opensearch_urls_lenient(&webpage, &url),
+            Err(Error::NoDescription(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn empty_and_html_descriptors() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/empty.xml"))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server)
+            .await;
+        Mock::given(path("/blank.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(" \n\t"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/html.xml"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("\n<!DOCTYPE html><html><body>Are you human?</body></html>"),
+            )
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let base = Url::parse(&server.uri()).unwrap();
+        let fetch = |name: &str| get_opensearch_raw(&fetcher, base.join(name).unwrap());
+
+        let error = fetch("/empty.xml").await.unwrap_err();
+        assert!(matches!(error, Error::EmptyDescription { .. }));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "OpenSearch description {} was empty (HTTP 204 No Content)",
+                base.join("/empty.xml").unwrap()
+            )
+        );
+
+        assert!(matches!(
+            fetch("/blank.xml").await,
+            Err(Error::EmptyDescription { .. })
+        ));
+
+        let error = fetch("/html.xml").await.unwrap_err();
+        assert!(matches!(error, Error::HtmlDescription { .. }));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{} returned an HTML page (HTTP 200 OK) instead of an OpenSearch description",
+                base.join("/html.xml").unwrap()
+            )
+        );
+
+        assert!(!is_html_document(
+            "<?xml version=\"1.0\"?><OpenSearchDescription/>"
+        ));
+        assert!(is_html_document("<HTML lang=\"en\">"));
+    }
+
+    #[test]
+    fn meta_charsets() {
+        let charset = |html: &str| html_meta_charset(html.as_bytes()).map(<[u8]>::to_vec);
+
+        assert_eq!(
+            charset(r#"<meta charset="Shift_JIS">"#),
+            Some(b"Shift_JIS".to_vec())
+        );
+        assert_eq!(charset("<META CHARSET=euc-jp/>"), Some(b"euc-jp".to_vec()));
+        assert_eq!(
+            charset(
+                r#"<meta name="x"><meta http-equiv="Content-Type" content="text/html; charset=windows-1252">"#
+            ),
+            Some(b"windows-1252".to_vec())
+        );
+        assert_eq!(
+            charset(r#"<meta name="viewport" content="width=device-width">"#),
+            None
+        );
+        assert_eq!(
+            charset(&format!("{}<meta charset=utf-8>", " ".repeat(2000))),
+            None
+        );
+    }
+
+    #[test]
+    fn utf_16_charsets() {
+        let page = "<html><head><title>検索</title></head></html>";
+        let utf_16 = page
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+
+        assert_eq!(decode_html(&utf_16, Some("UTF-16LE")), page);
+
+        let meta = r#"<html><head><meta charset="utf-16"><title>検索</title></head></html>"#;
+        assert_eq!(decode_html(meta.as_bytes(), None), meta);
+    }
+
+    #[tokio::test]
+    async fn shift_jis_webpage_decoded() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let page = r#"<html><head>
+            <meta charset="Shift_JIS">
+            <title>検索</title>
+            <link rel="search" type="application/opensearchdescription+xml" href="/検索.xml">
+        </head></html>"#;
+
+        let server = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "text/html")
+                    .set_body_bytes(encoding_rs::SHIFT_JIS.encode(page).0.into_owned()),
+            )
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let url = Url::parse(&server.uri()).unwrap();
+        let (_, webpage) = get_webpage_raw(&fetcher, url.clone()).await.unwrap();
+
+        assert!(webpage.contains("<title>検索</title>"));
+        assert_eq!(
+            select_opensearch_url(&parse_webpage(webpage), &url).unwrap(),
+            url.join("/検索.xml").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn latin1_descriptor_decoded() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let latin1 = |xml: &str| {
+            xml.chars()
+                .map(|character| u8::try_from(u32::from(character)).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(path("/header.xml"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(
+                        "content-type",
+                        "application/opensearchdescription+xml; charset=ISO-8859-1",
+                    )
+                    .set_body_bytes(latin1(
+                        "<OpenSearchDescription><Description>café</Description></OpenSearchDescription>",
+                    )),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/declaration.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(latin1(
+                "<?xml version=\"1.0\" encoding='ISO-8859-1'?><OpenSearchDescription><Description>café</Description></OpenSearchDescription>",
+            )))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let base = Url::parse(&server.uri()).unwrap();
+
+        for url in ["/header.xml", "/declaration.xml"] {
+            let raw = get_opensearch_raw(&fetcher, base.join(url).unwrap())
+                .await
+                .unwrap();
+            assert!(raw.contains("<Description>café</Description>"), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn xml_declared_encodings() {
+        assert_eq!(
+            xml_declared_encoding(b"<?xml version=\"1.0\" encoding = \"UTF-16\" ?><a/>"),
+            Some(&b"UTF-16"[..])
+        );
+        assert_eq!(xml_declared_encoding(b"<?xml version=\"1.0\"?><a/>"), None);
+        assert_eq!(xml_declared_encoding(b"<a encoding=\"x\"/>"), None);
+        assert_eq!(decode_xml("caf\u{e9}".as_bytes(), None), "caf\u{e9}");
+    }
+
+    #[test]
+    fn sniffed_image_types() {
+        let sniff = |bytes: &[u8]| sniff_image_type(bytes).map(|mime| mime.to_string());
+
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...").as_deref(), Some("image/png"));
+        assert_eq!(sniff(&[0, 0, 1, 0, 1, 0]).as_deref(), Some("image/x-icon"));
+        assert_eq!(sniff(b"GIF89a").as_deref(), Some("image/gif"));
+        assert_eq!(
+            sniff(b"RIFF\0\0\0\0WEBPVP8 ").as_deref(),
+            Some("image/webp")
+        );
+        assert_eq!(
+            sniff(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>")
+                .as_deref(),
+            Some("image/svg+xml")
+        );
+        assert_eq!(sniff(b"plain text"), None);
+    }
+
+    #[test]
+    fn icon_data_uris() {
+        assert_eq!(
+            icon_data_uri(&"image/png; charset=binary".parse().unwrap(), b"icon"),
+            "data:image/png;base64,aWNvbg=="
+        );
+    }
+
+    #[test]
+    fn retry_backoff_doubles() {
+        assert_eq!(retry_backoff(0), Duration::from_millis(500));
+        assert_eq!(retry_backoff(1), Duration::from_secs(1));
+        assert_eq!(retry_backoff(2), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn missing_meta_tag() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let webpage = parse_webpage("<html><head><title>Test</title></head></html>");
+        let error = select_opensearch_url(&webpage, &url).unwrap_err();
+
+        assert!(matches!(error, Error::NoDescription(_)));
+        assert_eq!(
+            error.to_string(),
+            "no OpenSearch description found at https://example.com/"
+        );
+    }
+
+    #[test]
+    fn meta_tag_case_insensitive() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let webpage = parse_webpage(
+            r#"<html><head><LINK REL=" SEARCH " TYPE="APPLICATION/OpenSearchDescription+XML; charset=utf-8" HREF="/opensearch.xml"></head></html>"#,
+        );
+
+        assert_eq!(
+            select_opensearch_url(&webpage, &url).unwrap().as_str(),
+            "https://example.com/opensearch.xml"
+        );
+    }
+
+    #[test]
+    fn meta_tag_attribute_matching() {
+        let matcher = LinkMatcher::default();
+        assert!(matcher.is_search_rel("search"));
+        assert!(matcher.is_search_rel("Search alternate"));
+        assert!(!matcher.is_search_rel("searchengine"));
+        assert!(matcher.is_opensearch_type("application/opensearchdescription+xml"));
+        assert!(matcher.is_opensearch_type(" application/opensearchdescription+xml ;charset=UTF-8"));
+        assert!(!matcher.is_opensearch_type("application/xml"));
+    }
+
+    #[test]
+    fn custom_link_matcher() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let webpage = parse_webpage(
+            r#"<html><head>
+                <link rel="search" type="application/opensearchdescription+xml" href="/standard.xml">
+                <link rel="search" type="application/x-vendor-search+xml" href="/vendor.xml">
+                <link rel="x-search" type="application/x-vendor-search+xml" href="/both.xml">
+            </head></html>"#,
+        );
+
+        let vendor_type = LinkMatcher {
+            link_type: "application/x-vendor-search+xml".to_string(),
+            ..LinkMatcher::default()
+        };
+        assert_eq!(
+            select_opensearch_urls_with(&webpage, &url, &vendor_type).unwrap(),
+            [url.join("/vendor.xml").unwrap()]
+        );
+
+        let vendor_rel = LinkMatcher {
+            rel: "x-search".to_string(),
+            ..vendor_type
+        };
+        assert_eq!(
+            select_opensearch_urls_with(&webpage, &url, &vendor_rel).unwrap(),
+            [url.join("/both.xml").unwrap()]
+        );
+
+        assert_eq!(
+            select_opensearch_urls(&webpage, &url).unwrap(),
+            [url.join("/standard.xml").unwrap()]
+        );
+    }
+
+    #[test]
+    fn meta_tag_outside_head() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let webpage = parse_webpage(
+            r#"<html><body><div><p>Search</p><link rel="search" type="application/opensearchdescription+xml" href="/body.xml"></div></body></html>"#,
+        );
+
+        assert_eq!(
+            select_opensearch_url(&webpage, &url).unwrap().as_str(),
+            "https://example.com/body.xml"
+        );
+    }
+
+    #[test]
+    fn first_meta_tag_selected() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let webpage = parse_webpage(
+            r#"<html><head>
+                <link rel="stylesheet" type="text/css" href="/style.css">
+                <link rel="search" type="application/opensearchdescription+xml" href="/first.xml">
+                <link rel="search" type="application/opensearchdescription+xml" href="/second.xml">
+            </head></html>"#,
+        );
+
+        assert_eq!(
+            select_opensearch_url(&webpage, &url).unwrap().as_str(),
+            "https://example.com/first.xml"
+        );
+    }
+
+    #[test]
+    fn all_meta_tags_selected() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let webpage = parse_webpage(
+            r#"<html><head>
+                <link rel="search" type="application/opensearchdescription+xml" href="/de.xml">
+                <link rel="search" type="application/opensearchdescription+xml" href="/en.xml">
+            </head></html>"#,
+        );
+
+        let urls = select_opensearch_urls(&webpage, &url).unwrap();
+
+        assert_eq!(
+            urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            ["https://example.com/de.xml", "https://example.com/en.xml"]
+        );
+    }
+
+    #[test]
+    fn relative_meta_tag() {
+        let url = Url::parse("https://example.com/search/").unwrap();
+        let webpage = parse_webpage(
+            r#"<html><head><link rel="search" type="application/opensearchdescription+xml" href="opensearch.xml"></head></html>"#,
+        );
+
+        assert_eq!(
+            select_opensearch_url(&webpage, &url).unwrap().as_str(),
+            "https://example.com/search/opensearch.xml"
+        );
+    }
+}
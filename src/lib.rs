@@ -0,0 +1,394 @@
+//! Converts OpenSearch description documents into NixOS search engine definitions.
+
+#[cfg(feature = "network")]
+pub mod fetch;
+mod nix;
+mod opensearch;
+mod output;
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use url::Url;
+use xml::{
+    namespace::{NS_EMPTY_URI, NS_XMLNS_PREFIX, NS_XML_PREFIX},
+    reader::{ParserConfig2, XmlEvent},
+    Encoding, EventReader, ParserConfig,
+};
+
+use opensearch::OpenSearchDescriptionXml;
+
+pub use nix::{
+    compact_nix, escape_nix_string, slugify, IndentStyle, NixOptions, ParamsShape, QueryStyle,
+};
+pub use opensearch::{
+    OpenSearchDescription, OpenSearchImage, OpenSearchMethod, OpenSearchParam, OpenSearchQuery,
+    OpenSearchRel, OpenSearchUrl, DEFAULT_OFFSET, MAX_SHORT_NAME_LENGTH, SUBSTITUTED_PLACEHOLDERS,
+};
+pub use output::{EngineOutput, UrlOutput};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[cfg(feature = "network")]
+    #[error("failed to build http client: {0}")]
+    BuildClient(reqwest::Error),
+    #[cfg(feature = "network")]
+    #[error("failed to fetch webpage {url}: {source}")]
+    FetchWebpage { url: Url, source: reqwest::Error },
+    #[cfg(feature = "network")]
+    #[error("failed to fetch webpage {url}: HTTP {status}")]
+    WebpageStatus {
+        url: Url,
+        status: reqwest::StatusCode,
+    },
+    #[error("no OpenSearch description found at {0}")]
+    NoDescription(Url),
+    #[error("OpenSearch link on {0} is missing an href attribute")]
+    MissingDescriptionHref(Url),
+    #[error("incorrectly formatted OpenSearch url {href:?}: {source}")]
+    InvalidDescriptionUrl {
+        href: String,
+        source: url::ParseError,
+    },
+    #[cfg(feature = "network")]
+    #[error("failed to fetch OpenSearch description {url}: {source}")]
+    FetchDescription { url: Url, source: reqwest::Error },
+    #[cfg(feature = "network")]
+    #[error("descriptor not found (404) at {0}")]
+    DescriptionNotFound(Url),
+    #[cfg(feature = "network")]
+    #[error("failed to fetch OpenSearch description {url}: HTTP {status}")]
+    DescriptionStatus {
+        url: Url,
+        status: reqwest::StatusCode,
+    },
+    #[cfg(feature = "network")]
+    #[error("OpenSearch description {url} was empty (HTTP {status})")]
+    EmptyDescription {
+        url: Url,
+        status: reqwest::StatusCode,
+    },
+    #[cfg(feature = "network")]
+    #[error("{url} returned an HTML page (HTTP {status}) instead of an OpenSearch description")]
+    HtmlDescription {
+        url: Url,
+        status: reqwest::StatusCode,
+    },
+    #[cfg(feature = "network")]
+    #[error("failed to read local file {url}: {source}")]
+    ReadFileUrl { url: Url, source: std::io::Error },
+    #[cfg(feature = "network")]
+    #[error("{url} redirected more than {max} times")]
+    TooManyRedirects { url: Url, max: usize },
+    #[cfg(feature = "network")]
+    #[error("failed to fetch icon {url}: {source}")]
+    FetchIcon { url: Url, source: reqwest::Error },
+    #[cfg(feature = "network")]
+    #[error("failed to query {url}: {source}")]
+    FetchQuery { url: Url, source: reqwest::Error },
+    #[cfg(feature = "network")]
+    #[error("icon {url} is {size} bytes, over the {max} byte limit")]
+    IconTooLarge { url: Url, size: u64, max: u64 },
+    #[error("incorrectly formatted image url {href:?}: {source}")]
+    InvalidImageUrl {
+        href: String,
+        source: url::ParseError,
+    },
+    #[error("incorrectly formatted url template {template:?}: {source}")]
+    InvalidTemplateUrl {
+        template: String,
+        source: url::ParseError,
+    },
+    #[error(
+        "<Url template={template:?}> has an incorrectly formatted type {template_type:?}: {source}"
+    )]
+    InvalidUrlType {
+        template: String,
+        template_type: String,
+        source: mime::FromStrError,
+    },
+    #[error("url template {template:?} uses the unsupported scheme {scheme:?}")]
+    UnsupportedScheme { template: String, scheme: String },
+    #[error("failed to deserialize OpenSearch xml data: {0}")]
+    Deserialize(#[from] serde_xml_rs::Error),
+    #[error(
+        "short name {short_name:?} is {length} characters; OpenSearch allows at most {}",
+        opensearch::MAX_SHORT_NAME_LENGTH
+    )]
+    ShortNameTooLong { short_name: String, length: usize },
+    #[error("description has no ShortName to name the engine by")]
+    EmptyShortName,
+    #[error("{short_name:?} has no url of type {url_type} (found: {found})")]
+    UrlTypeNotFound {
+        short_name: String,
+        url_type: String,
+        found: String,
+    },
+    #[error("{short_name:?} defines no search results url (found: {found})")]
+    NoResultsUrl { short_name: String, found: String },
+    #[error("failed to write Nix output")]
+    WriteNix(#[from] std::fmt::Error),
+}
+
+/// Parses an OpenSearch description document whose urls must all be absolute.
+pub fn parse_opensearch(xml: &str) -> Result<OpenSearchDescription, Error> {
+    deserialize_opensearch_xml(xml)?.resolve(None, false)
+}
+
+/// Parses an OpenSearch description document, resolving relative urls against `base`, the url
+/// the document was fetched from.
+pub fn parse_opensearch_with_base(xml: &str, base: &Url) -> Result<OpenSearchDescription, Error> {
+    deserialize_opensearch_xml(xml)?.resolve(Some(base), false)
+}
+
+/// Parses an OpenSearch description document like [`parse_opensearch_with_base`], but leaves out
+/// any `<Url>` with an invalid type or template, noting it in the description's warnings.
+pub fn parse_opensearch_skipping_invalid(
+    xml: &str,
+    base: Option<&Url>,
+) -> Result<OpenSearchDescription, Error> {
+    deserialize_opensearch_xml(xml)?.resolve(base, true)
+}
+
+/// Converts an OpenSearch description document straight to a Nix attribute, for callers such as
+/// WebAssembly modules that can only pass strings across.
+pub fn xml_to_nix(xml: &str) -> Result<String, String> {
+    parse_opensearch(xml)
+        .and_then(|opensearch| opensearch.to_nix())
+        .map_err(|error| error.to_string())
+}
+
+fn deserialize_opensearch_xml(xml: &str) -> Result<OpenSearchDescriptionXml, Error> {
+    // The text has already been decoded, so any encoding its declaration names no longer applies.
+    let config = ParserConfig::new()
+        .trim_whitespace(true)
+        .whitespace_to_characters(true)
+        .cdata_to_characters(true)
+        .ignore_comments(true)
+        .coalesce_characters(true)
+        .override_encoding(Some(Encoding::Utf8))
+        .ignore_invalid_encoding_declarations(true);
+    let mut deserializer = serde_xml_rs::Deserializer::new(EventReader::new_with_config(
+        xml.as_bytes(),
+        config.clone(),
+    ));
+
+    let mut description = OpenSearchDescriptionXml::deserialize(&mut deserializer)?;
+    description.namespaces = root_namespaces(xml, config);
+    Ok(description)
+}
+
+/// The namespaces declared on the root element by prefix, with any default namespace under an
+/// empty prefix. Deserializing doesn't expose them, so they're read in a pass of their own.
+fn root_namespaces(xml: &str, config: ParserConfig2) -> BTreeMap<String, String> {
+    let root = EventReader::new_with_config(xml.as_bytes(), config)
+        .into_iter()
+        .find_map(|event| match event {
+            Ok(XmlEvent::StartElement { namespace, .. }) => Some(Ok(namespace)),
+            Ok(_) => None,
+            Err(error) => Some(Err(error)),
+        });
+
+    let Some(Ok(namespace)) = root else {
+        return BTreeMap::new();
+    };
+
+    namespace
+        .into_iter()
+        .filter(|(prefix, uri)| {
+            !matches!(*prefix, NS_XML_PREFIX | NS_XMLNS_PREFIX) && *uri != NS_EMPTY_URI
+        })
+        .map(|(prefix, uri)| (prefix.to_string(), uri.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn root_namespaces() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/" xmlns:moz="http://www.mozilla.org/2006/browser/search/">
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <moz:SearchForm>https://example.com/</moz:SearchForm>
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        assert_eq!(
+            parsed.namespaces,
+            BTreeMap::from([
+                (
+                    String::new(),
+                    "http://a9.com/-/spec/opensearch/1.1/".to_string()
+                ),
+                (
+                    "moz".to_string(),
+                    "http://www.mozilla.org/2006/browser/search/".to_string()
+                ),
+            ])
+        );
+        assert_eq!(parsed.search_form.unwrap().as_str(), "https://example.com/");
+
+        let bare = parse_opensearch(
+            r#"<OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>"#,
+        )
+        .unwrap();
+        assert!(bare.namespaces.is_empty());
+    }
+
+    #[test]
+    fn rerun_is_identical() {
+        let description = |images: &[&str]| {
+            format!(
+                r#"<?xml version="1.0"?>
+                <OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/" xmlns:moz="http://www.mozilla.org/2006/browser/search/" xmlns:ie="http://schemas.microsoft.com/Search/2008/">
+                    <ShortName>Test</ShortName>
+                    {}
+                    <Url type="text/html" template="https://example.com/search?z={{searchTerms}}&amp;a=1&amp;m={{startPage?}}">
+                        <Param name="b" value="2" />
+                    </Url>
+                    <Url type="application/x-suggestions+json" template="https://example.com/json?q={{searchTerms}}" />
+                </OpenSearchDescription>"#,
+                images.concat()
+            )
+        };
+        let webp = r#"<Image height="16" width="16" type="image/webp">https://example.com/icon.webp</Image>"#;
+        let gif = r#"<Image height="16" width="16" type="image/gif">https://example.com/icon.gif</Image>"#;
+        let typeless = r#"<Image height="16" width="16">https://example.com/icon</Image>"#;
+
+        let options = NixOptions {
+            all_icons: true,
+            ..NixOptions::default()
+        };
+        let generate = |raw: &str| {
+            let parsed = parse_opensearch(raw).unwrap();
+            (
+                parsed.to_nix_with(&options).unwrap(),
+                format!("{:?}", parsed),
+            )
+        };
+
+        let raw = description(&[typeless, webp, gif]);
+        let (nix, debug) = generate(&raw);
+        assert_eq!(generate(&raw), (nix.clone(), debug));
+        assert!(nix.contains("iconUpdateURL = \"https://example.com/icon.gif\";"));
+
+        // Icons that rank alike are picked by type, not by the order they're listed in.
+        assert_eq!(generate(&description(&[gif, typeless, webp])).0, nix);
+
+        // Icons of the same type and size are picked by url.
+        let a =
+            r#"<Image height="16" width="16" type="image/png">https://example.com/a.png</Image>"#;
+        let b =
+            r#"<Image height="16" width="16" type="image/png">https://example.com/b.png</Image>"#;
+        let nix = generate(&description(&[a, b])).0;
+        assert!(nix.contains("iconUpdateURL = \"https://example.com/a.png\";"));
+        assert_eq!(generate(&description(&[b, a])).0, nix);
+    }
+
+    #[test]
+    fn cdata_text() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName><![CDATA[Test]]></ShortName>
+                <Description><![CDATA[A & B]]></Description>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        assert_eq!(parsed.short_name, "Test");
+        assert_eq!(parsed.description, "A & B");
+
+        // Escaped markers survive xml parsing as literal text.
+        let escaped = raw.replace("<![CDATA[A & B]]>", "&lt;![CDATA[A &amp; B]]&gt;");
+        assert_eq!(parse_opensearch(&escaped).unwrap().description, "A & B");
+    }
+
+    #[test]
+    fn parse_to_nix() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Description>Hi there</Description>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        assert_eq!(
+            parse_opensearch(raw).unwrap().to_nix().unwrap(),
+            r#""Test" = {
+    urls = [
+        {
+            template = "https://example.com/search";
+            type = "text/html";
+            params = [
+                {
+                    name = "q";
+                    value = "{searchTerms}";
+                }
+            ];
+        }
+    ];
+    description = "Hi there";
+};"#
+        );
+    }
+
+    #[test]
+    fn xml_to_nix_strings() {
+        let raw = r#"<OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>"#;
+
+        assert!(xml_to_nix(raw).unwrap().starts_with("\"Test\" = {\n"));
+        assert!(xml_to_nix("<OpenSearchDescription>")
+            .unwrap_err()
+            .starts_with("failed to deserialize OpenSearch xml data"));
+    }
+
+    #[test]
+    fn suggestions_only() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="application/x-suggestions+json" template="https://example.com/json?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let error = parse_opensearch(raw).unwrap().to_nix().unwrap_err();
+
+        assert!(matches!(error, Error::NoResultsUrl { .. }));
+        assert_eq!(
+            error.to_string(),
+            "\"Test\" defines no search results url (found: application/x-suggestions+json)"
+        );
+    }
+
+    #[test]
+    fn declared_encoding_after_decoding() {
+        let raw = r#"<?xml version="1.0" encoding="ISO-8859-1"?>
+            <OpenSearchDescription>
+                <ShortName>Café</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        assert_eq!(parse_opensearch(raw).unwrap().short_name, "Café");
+    }
+
+    #[test]
+    fn invalid_xml() {
+        assert!(matches!(
+            parse_opensearch("not xml"),
+            Err(Error::Deserialize(_))
+        ));
+    }
+}
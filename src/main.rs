@@ -1,307 +1,3275 @@
-use std::cell::OnceCell;
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    future::Future,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use clap::Parser;
+use clap::{
+    parser::ValueSource, ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand,
+    ValueEnum,
+};
+use clap_complete::Shell;
+use futures::future::{join_all, try_join_all};
 use mime::Mime;
-use reqwest::Url;
-use scraper::Html;
+use nix_opensearch_generator::{
+    compact_nix,
+    fetch::{
+        get_icon_data_uri, get_opensearch_raw, get_query_status, get_webpage_raw,
+        looks_script_rendered, parse_webpage, select_opensearch_urls_lenient_with,
+        select_opensearch_urls_with, Fetcher, FetcherConfig, LinkMatcher, RedirectHook,
+        DEFAULT_MAX_REDIRECTS, DEFAULT_RETRIES, DEFAULT_TIMEOUT, DEFAULT_USER_AGENT, META_TAG_REL,
+        META_TAG_TYPE,
+    },
+    parse_opensearch, parse_opensearch_skipping_invalid, parse_opensearch_with_base, slugify,
+    EngineOutput, IndentStyle, NixOptions, OpenSearchDescription, OpenSearchImage,
+    OpenSearchMethod, OpenSearchRel, OpenSearchUrl, ParamsShape, QueryStyle,
+};
+use reqwest::header::{HeaderName, HeaderValue};
 use serde::Deserialize;
-use serde_with::{rust::deserialize_ignore_any, serde_as, DisplayFromStr};
+use similar::TextDiff;
+use url::Url;
 
-const META_TAG_REL: &str = "search";
-const META_TAG_TYPE: &str = "application/opensearchdescription+xml";
+/// Where --module puts the engines unless --module-path says otherwise.
+const DEFAULT_MODULE_PATH: &str = "programs.firefox.profiles.default.search.engines";
 
-#[derive(Debug, Deserialize, Clone)]
-#[serde(from = "OpenSearchDescriptionXml")]
-struct OpenSearchDescription {
-    short_name: String,
-    description: String,
-    images: Vec<OpenSearchImage>,
-    urls: Vec<OpenSearchUrl>,
+/// The placeholder --substitute-locale fills in.
+const LANGUAGE_PLACEHOLDER: &str = "language";
+
+/// What --validate-live searches for when a description gives no example query.
+const DEFAULT_LIVE_SEARCH_TERMS: &str = "test";
+
+/// The placeholder for how many results a page should hold.
+const COUNT_PLACEHOLDER: &str = "count";
+
+/// The `--input-file` path that reads from stdin instead.
+const STDIN_PATH: &str = "-";
+
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error(transparent)]
+    OpenSearch(#[from] nix_opensearch_generator::Error),
+    #[error("{input} uses relative urls; pass --base-url to resolve them ({error})")]
+    MissingBaseUrl {
+        input: String,
+        error: Box<nix_opensearch_generator::Error>,
+    },
+    #[error("failed to read OpenSearch description {path}: {source}")]
+    ReadDescriptionFile { path: PathBuf, source: io::Error },
+    #[error("--select {index} is out of range; {website} links {count} OpenSearch description(s)")]
+    SelectOutOfRange {
+        index: usize,
+        count: usize,
+        website: Url,
+    },
+    #[error(
+        "no OpenSearch description found at {0}; it looks like a JavaScript app, so the \
+        descriptor may be injected via JavaScript; try --descriptor-url"
+    )]
+    ScriptRendered(Url),
+    #[error("{first} and {second} both resolve to the attribute name {attr_name:?}")]
+    DuplicateAttrName {
+        attr_name: String,
+        first: String,
+        second: String,
+    },
+    #[error("failed to serialize json output: {0}")]
+    SerializeJson(#[from] serde_json::Error),
+    #[error("failed to serialize yaml output: {0}")]
+    SerializeYaml(#[from] serde_yaml::Error),
+    #[error("output file {0} already exists; use --force to overwrite it")]
+    OutputExists(PathBuf),
+    #[error("failed to write output file {path}: {source}")]
+    WriteOutput { path: PathBuf, source: io::Error },
+    #[error("failed to read urls file {path}: {source}")]
+    ReadUrlsFile { path: PathBuf, source: io::Error },
+    #[error("invalid website url {website:?}: {source}")]
+    InvalidWebsite {
+        website: String,
+        source: url::ParseError,
+    },
+    #[error("{failed} website(s) from the urls file failed")]
+    BatchFailed { failed: usize },
+    #[error("failed to read {path} to diff against: {source}")]
+    ReadDiffFile { path: PathBuf, source: io::Error },
+    #[error("generated output differs from {0}")]
+    DiffFound(PathBuf),
+    #[error("{failed} of {total} live test query(s) failed")]
+    LiveQueryFailed { failed: usize, total: usize },
+    #[error("{failed} of {total} OpenSearch description(s) failed the check")]
+    CheckFailed { failed: usize, total: usize },
+    #[error("failed to read config file {path}: {source}")]
+    ReadConfig { path: PathBuf, source: io::Error },
+    #[error("invalid config file {path}: {source}")]
+    ParseConfig {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("{descriptor} has no ShortName and no host to name the engine after")]
+    NoFallbackName { descriptor: String },
+    #[error("aborted")]
+    Aborted,
 }
 
-impl OpenSearchDescription {
-    fn into_nix(&self, buf: &mut String) {
-        assert!(
-            !self.urls.is_empty(),
-            "OpenSearch requires at least one defined URL; none were found."
-        );
+/// The exit code for any failure without a more specific code.
+const FAILURE_EXIT_CODE: u8 = 1;
+/// The exit code for a website, description or icon that couldn't be fetched.
+const NETWORK_EXIT_CODE: u8 = 2;
+/// The exit code for a website that doesn't link an OpenSearch description.
+const DISCOVERY_EXIT_CODE: u8 = 3;
+/// The exit code for a description that couldn't be parsed.
+const PARSE_EXIT_CODE: u8 = 4;
+/// The exit code for a description that parsed but can't be used as a search engine.
+const VALIDATION_EXIT_CODE: u8 = 5;
+/// The exit code for a run cancelled by Ctrl-C, following the shell's 128 + SIGINT.
+const ABORTED_EXIT_CODE: u8 = 130;
+
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0    success
+  1    any other failure
+  2    network error
+  3    no OpenSearch description discovered
+  4    description parse error
+  5    description validation error
+  130  aborted by Ctrl-C";
+
+/// The exit code for a failure class, so scripts can branch on why a run failed.
+fn exit_code(error: &Error) -> u8 {
+    use nix_opensearch_generator::Error as OpenSearchError;
 
-        *buf += &format!("\"{}\" = {{\n    urls = [\n", self.short_name);
+    match error {
+        Error::OpenSearch(error) => match error {
+            OpenSearchError::BuildClient(_)
+            | OpenSearchError::FetchWebpage { .. }
+            | OpenSearchError::WebpageStatus { .. }
+            | OpenSearchError::FetchDescription { .. }
+            | OpenSearchError::DescriptionNotFound(_)
+            | OpenSearchError::DescriptionStatus { .. }
+            | OpenSearchError::FetchQuery { .. }
+            | OpenSearchError::FetchIcon { .. } => NETWORK_EXIT_CODE,
+            #[cfg(feature = "network")]
+            OpenSearchError::ReadFileUrl { .. } => NETWORK_EXIT_CODE,
+            #[cfg(feature = "network")]
+            OpenSearchError::TooManyRedirects { .. } => NETWORK_EXIT_CODE,
+            #[cfg(feature = "network")]
+            OpenSearchError::IconTooLarge { .. } => NETWORK_EXIT_CODE,
+            OpenSearchError::NoDescription(_)
+            | OpenSearchError::MissingDescriptionHref(_)
+            | OpenSearchError::InvalidDescriptionUrl { .. } => DISCOVERY_EXIT_CODE,
+            OpenSearchError::EmptyDescription { .. }
+            | OpenSearchError::HtmlDescription { .. }
+            | OpenSearchError::InvalidImageUrl { .. }
+            | OpenSearchError::InvalidTemplateUrl { .. }
+            | OpenSearchError::InvalidUrlType { .. }
+            | OpenSearchError::Deserialize(_) => PARSE_EXIT_CODE,
+            OpenSearchError::UnsupportedScheme { .. }
+            | OpenSearchError::ShortNameTooLong { .. }
+            | OpenSearchError::EmptyShortName
+            | OpenSearchError::UrlTypeNotFound { .. }
+            | OpenSearchError::NoResultsUrl { .. } => VALIDATION_EXIT_CODE,
+            OpenSearchError::WriteNix(_) => FAILURE_EXIT_CODE,
+        },
+        Error::SelectOutOfRange { .. } | Error::ScriptRendered(_) => DISCOVERY_EXIT_CODE,
+        Error::MissingBaseUrl { .. } => PARSE_EXIT_CODE,
+        Error::DuplicateAttrName { .. }
+        | Error::CheckFailed { .. }
+        | Error::LiveQueryFailed { .. }
+        | Error::NoFallbackName { .. } => VALIDATION_EXIT_CODE,
+        Error::Aborted => ABORTED_EXIT_CODE,
+        _ => FAILURE_EXIT_CODE,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Subcommand)]
+enum Command {
+    /// Converts a bundled sample description, without the network, to check the install works.
+    Selftest,
+}
 
-        self.urls.iter().for_each(|url| url.into_nix(buf));
+/// The description `selftest` converts.
+const SELFTEST_DESCRIPTOR: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+    <ShortName>Example</ShortName>
+    <Description>Search Example</Description>
+    <InputEncoding>UTF-8</InputEncoding>
+    <Image height="16" width="16" type="image/x-icon">https://example.com/favicon.ico</Image>
+    <Url type="text/html" template="https://example.com/search?q={searchTerms}&amp;source=opensearch" />
+    <Url type="application/x-suggestions+json" template="https://example.com/suggest?q={searchTerms}" />
+</OpenSearchDescription>
+"#;
+
+/// The Nix `selftest` expects from [`SELFTEST_DESCRIPTOR`].
+const SELFTEST_GOLDEN: &str = r#""Example" = {
+    urls = [
+        {
+            template = "https://example.com/search";
+            type = "text/html";
+            params = [
+                {
+                    name = "q";
+                    value = "{searchTerms}";
+                }
+                {
+                    name = "source";
+                    value = "opensearch";
+                }
+            ];
+        }
+        {
+            template = "https://example.com/suggest";
+            type = "application/x-suggestions+json";
+            params = [
+                {
+                    name = "q";
+                    value = "{searchTerms}";
+                }
+            ];
+        }
+    ];
+    iconUpdateURL = "https://example.com/favicon.ico";
+    description = "Search Example";
+};"#;
 
-        *buf += "    ];\n";
+/// Converts the bundled sample, returning the Nix, or the error, and whether it matched.
+fn selftest() -> (String, bool) {
+    match parse_opensearch(SELFTEST_DESCRIPTOR).and_then(|opensearch| opensearch.to_nix()) {
+        Ok(nix) => {
+            let passed = nix == SELFTEST_GOLDEN;
+            (nix, passed)
+        }
+        Err(error) => (format!("error: {}", error), false),
+    }
+}
 
-        let mut sorted_images = self.images.clone();
-        sorted_images.sort();
+/// How several Nix engines are collected together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum OutputShape {
+    /// An attrset keyed by name, as `search.engines` takes.
+    #[default]
+    Attrset,
+    /// A list with each engine's name as a field.
+    List,
+}
 
-        sorted_images
-            .into_iter()
-            .next()
-            .map(|image| image.into_nix(buf));
+/// How each url's params are emitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum ParamsShapeArg {
+    /// A list of name and value pairs.
+    #[default]
+    List,
+    /// An attrset keyed by name, where a repeated name keeps its last value.
+    Attrset,
+}
 
-        *buf += &format!("    description = \"{}\";\n}};", self.description);
+impl From<ParamsShapeArg> for ParamsShape {
+    fn from(shape: ParamsShapeArg) -> Self {
+        match shape {
+            ParamsShapeArg::List => Self::List,
+            ParamsShapeArg::Attrset => Self::Attrset,
+        }
     }
 }
 
-#[derive(Debug, Deserialize)]
-enum OpenSearchDescriptionXmlValue {
-    ShortName(String),
-    Description(String),
-    Image(OpenSearchImage),
-    Url(OpenSearchUrl),
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum OutputFormat {
+    #[default]
+    Nix,
+    Json,
+    Yaml,
+    /// A Firefox `policies.json` adding the engines.
+    FirefoxPolicy,
+}
+
+/// Fetches a html webpage and extracts the open-search protocol information.
+#[derive(Debug, Parser)]
+#[command(version, after_help = EXIT_CODES_HELP)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(group(
+    ArgGroup::new("source")
+        .args(["website", "input_file", "descriptor_url", "urls_file"])
+))]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The website urls to convert; `file://` urls read saved pages from disk.
+    // Stands in for the source group being required, which completions don't need.
+    #[arg(required_unless_present_any = [
+        "input_file",
+        "descriptor_url",
+        "urls_file",
+        "generate_completions",
+    ])]
+    website: Vec<Url>,
+
+    /// Reads the OpenSearch description from a local xml file instead of a website; `-` reads
+    /// it from stdin.
+    #[arg(long, short)]
+    input_file: Option<PathBuf>,
+
+    /// The url relative urls in an --input-file are resolved against.
+    #[arg(long, conflicts_with_all = ["website", "descriptor_url", "urls_file"])]
+    base_url: Option<Url>,
+
+    /// Fetches the OpenSearch description directly, skipping the website lookup.
+    #[arg(long, short)]
+    descriptor_url: Option<Url>,
+
+    /// Converts every website listed in a file, one url per line; `#` starts a comment.
+    #[arg(long)]
+    urls_file: Option<PathBuf>,
+
+    /// Also considers `rel="search"` links without the OpenSearch type, using the first that
+    /// serves a valid description.
+    #[arg(long, action)]
+    lenient_discovery: bool,
+
+    /// The `rel` a webpage's link to its OpenSearch description has.
+    #[arg(long, default_value = META_TAG_REL)]
+    rel: String,
+
+    /// The `type` a webpage's link to its OpenSearch description has.
+    #[arg(long = "type", value_name = "TYPE", default_value = META_TAG_TYPE)]
+    link_type: String,
+
+    /// Which OpenSearch description to use when a website links several, counting from 0.
+    #[arg(long)]
+    select: Option<usize>,
+
+    /// The format to emit the engines in.
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+
+    /// Whether Nix output collects the engines in an attrset or a list.
+    #[arg(long, value_enum, default_value_t)]
+    output_shape: OutputShape,
+
+    /// Writes the generated output to a file instead of stdout.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+
+    /// Ends the output without a newline instead of exactly one.
+    #[arg(long, action)]
+    no_final_newline: bool,
+
+    /// Allows overwriting an existing output file.
+    #[arg(long, short, action, requires = "output")]
+    force: bool,
+
+    /// Overrides the attribute name the engine is emitted under; defaults to its short name.
+    #[arg(long, short)]
+    attr_name: Option<String>,
+
+    /// Derives a lowercase, dash-separated attribute name from the short name.
+    #[arg(long, short, action, conflicts_with = "attr_name")]
+    slugify: bool,
+
+    /// How many spaces to indent each level of the generated Nix with.
+    #[arg(long, default_value_t = 4)]
+    indent: usize,
+
+    /// Indents the generated Nix with tabs instead of spaces.
+    #[arg(long, action, conflicts_with = "indent")]
+    tabs: bool,
+
+    /// A keyword that triggers the engine from the address bar; may be repeated.
+    #[arg(long)]
+    alias: Vec<String>,
+
+    /// Omits the alias otherwise derived from the short name when no --alias is given.
+    #[arg(long, action, conflicts_with = "alias")]
+    no_default_alias: bool,
+
+    /// Keeps each --alias as given instead of prefixing it with `@`.
+    #[arg(long, action)]
+    no_alias_prefix: bool,
+
+    /// Wraps the Nix output in a module that can be imported directly.
+    #[arg(long, action)]
+    module: bool,
+
+    /// The option the --module assigns the engines to.
+    #[arg(long, default_value = DEFAULT_MODULE_PATH, requires = "module")]
+    module_path: String,
+
+    /// Keeps each url's query string in its template instead of splitting it into params.
+    #[arg(long, action)]
+    no_query_split: bool,
+
+    /// Replaces the `{language}` placeholder, which Firefox can't fill in, with this locale.
+    #[arg(long, value_name = "LOCALE")]
+    substitute_locale: Option<String>,
+
+    /// Emits each url's params sorted by name, for stable diffs, instead of in template order.
+    #[arg(long, action)]
+    sort_params: bool,
+
+    /// Emits each url's params as a list or as an attrset keyed by name.
+    #[arg(long, value_enum, default_value_t)]
+    params_shape: ParamsShapeArg,
+
+    /// Emits params percent-encoded as written in the template instead of decoded.
+    #[arg(long, action, conflicts_with = "no_query_split")]
+    raw_params: bool,
+
+    /// Uses the site's /favicon.ico as the icon when the description has no images.
+    #[arg(long, action)]
+    fallback_favicon: bool,
+
+    /// Emits urls in the order the description lists them instead of sorting them.
+    #[arg(long, action)]
+    preserve_order: bool,
+
+    /// Emits only the urls of this MIME type; may be repeated.
+    #[arg(long, value_name = "MIME")]
+    url_type: Vec<Mime>,
 
-    #[serde(other, deserialize_with = "deserialize_ignore_any")]
-    Other,
+    /// Emits Atom and RSS feed urls, which are left out by default as Firefox can't use them.
+    #[arg(long, action)]
+    include_feeds: bool,
+
+    /// Emits urls that only differ by scheme or a trailing slash instead of collapsing them.
+    #[arg(long, action)]
+    keep_duplicates: bool,
+
+    /// Leaves out urls with an invalid type or template instead of failing the whole description.
+    #[arg(long, action)]
+    skip_invalid: bool,
+
+    /// Comments each engine with the description it was generated from and its search form.
+    #[arg(long, action)]
+    annotate: bool,
+
+    /// Comments each engine with its homepage, from its search form or where it was found.
+    #[arg(long, action)]
+    include_homepage: bool,
+
+    /// Emits the Nix on a single line, for embedding in a larger generated file.
+    #[arg(long, action, conflicts_with_all = ["annotate", "include_homepage", "all_icons"])]
+    compact: bool,
+
+    /// Lists the icons not chosen for the engine in a comment after it.
+    #[arg(long, action)]
+    all_icons: bool,
+
+    /// How many days Firefox waits before refreshing a linked icon.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    icon_update_interval: Option<u32>,
+
+    /// Downloads the icon and embeds it as a data uri instead of linking to it.
+    #[arg(long, action)]
+    inline_icon: bool,
+
+    /// The most bytes an --inline-icon may be; a larger one gives way to a smaller icon, or to
+    /// linking it when none fits.
+    #[arg(long, value_name = "BYTES", requires = "inline_icon")]
+    max_icon_size: Option<u64>,
+
+    /// Permits url templates with this scheme besides http and https; may be repeated.
+    #[arg(long)]
+    allow_scheme: Vec<String>,
+
+    /// Treats a short name longer than the spec allows as an error instead of a warning.
+    #[arg(long, action)]
+    strict: bool,
+
+    /// Prints the url of every OpenSearch description the websites link instead of converting them.
+    #[arg(
+        long,
+        action,
+        requires = "website",
+        conflicts_with_all = ["input_file", "descriptor_url", "urls_file", "check", "output"]
+    )]
+    print_descriptor_url: bool,
+
+    /// Prints a diff from this existing file to the generated output instead of emitting it,
+    /// failing if they differ.
+    #[arg(long, value_name = "EXISTING", conflicts_with_all = ["output", "check"])]
+    diff: Option<PathBuf>,
+
+    /// Ignores changes in indentation, spacing and blank lines when diffing.
+    #[arg(long, action, requires = "diff")]
+    ignore_whitespace: bool,
+
+    /// Validates the OpenSearch descriptions and prints a summary instead of emitting output.
+    #[arg(long, action, conflicts_with = "output")]
+    check: bool,
+
+    /// Runs a test query against each url, with the description's example search terms or
+    /// "test", and reports the HTTP status; fails if any query doesn't succeed.
+    #[arg(long, action)]
+    validate_live: bool,
+
+    /// Seconds to wait for each request before giving up.
+    #[arg(long, default_value_t = DEFAULT_TIMEOUT.as_secs())]
+    timeout: u64,
+
+    /// How many times to retry a request that timed out or hit a server error.
+    #[arg(long, default_value_t = DEFAULT_RETRIES)]
+    retries: u32,
+
+    /// The User-Agent header to send with every request.
+    #[arg(long, default_value = DEFAULT_USER_AGENT)]
+    user_agent: String,
+
+    /// A header to send with every request, as "Name: Value"; may be given more than once.
+    #[arg(long = "header", value_name = "HEADER", value_parser = parse_header)]
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// How many redirects a request may follow before it fails.
+    #[arg(long, default_value_t = DEFAULT_MAX_REDIRECTS)]
+    max_redirects: usize,
+
+    /// A TOML file of defaults for --user-agent, --timeout, --indent, --format and
+    /// --module-path; flags given on the command line take precedence.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Prints a completion script for the shell instead of converting anything.
+    #[arg(long, value_name = "SHELL", exclusive = true)]
+    generate_completions: Option<Shell>,
+
+    #[arg(long, short, action)]
+    verbose: bool,
+
+    /// Prints each fully parsed description to stderr, for finding out why a field is missing.
+    #[arg(long, action, conflicts_with = "quiet")]
+    debug_parsed: bool,
+
+    /// Prints nothing but the generated output; failures are only reported by the exit code.
+    #[arg(long, short, action, conflicts_with = "verbose")]
+    quiet: bool,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename = "OpenSearchDescription")]
-struct OpenSearchDescriptionXml {
-    #[serde(rename = "$value")]
-    values: Vec<OpenSearchDescriptionXmlValue>,
+/// Parses a `--header` of the form `Name: Value`.
+fn parse_header(raw: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = raw
+        .split_once(':')
+        .ok_or_else(|| format!("{:?} isn't of the form \"Name: Value\"", raw))?;
+    let name = HeaderName::try_from(name.trim())
+        .map_err(|_| format!("{:?} isn't a valid header name", name.trim()))?;
+    let value = HeaderValue::try_from(value.trim())
+        .map_err(|_| format!("{:?} isn't a valid value for {}", value.trim(), name))?;
+
+    Ok((name, value))
 }
 
-impl From<OpenSearchDescriptionXml> for OpenSearchDescription {
-    fn from(value: OpenSearchDescriptionXml) -> Self {
-        let mut images = Vec::new();
-        let mut urls = Vec::new();
-        let short_name = OnceCell::new();
-        let description = OnceCell::new();
+/// Reads a description file, or stdin when the path is `-`.
+fn read_opensearch_file(path: &Path) -> Result<String, Error> {
+    let read_error = |source| Error::ReadDescriptionFile {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    if path == Path::new(STDIN_PATH) {
+        let mut raw = String::new();
+        io::stdin().read_to_string(&mut raw).map_err(read_error)?;
+        Ok(raw)
+    } else {
+        std::fs::read_to_string(path).map_err(read_error)
+    }
+}
 
-        for xml_value in value.values {
-            match xml_value {
-                OpenSearchDescriptionXmlValue::Url(url) => urls.push(url),
-                OpenSearchDescriptionXmlValue::Image(image) => images.push(image),
-                OpenSearchDescriptionXmlValue::ShortName(provided_name) => short_name
-                    .set(provided_name)
-                    .expect("Multiple short name values were provided"),
-                OpenSearchDescriptionXmlValue::Description(provided_description) => description
-                    .set(provided_description)
-                    .expect("Multiple descriptions were provided"),
-                OpenSearchDescriptionXmlValue::Other => (),
+fn choose_opensearch_url(
+    opensearch_urls: &[Url],
+    website: &Url,
+    select: Option<usize>,
+    quiet: bool,
+) -> Result<Url, Error> {
+    match select {
+        Some(index) => opensearch_urls
+            .get(index)
+            .cloned()
+            .ok_or_else(|| Error::SelectOutOfRange {
+                index,
+                count: opensearch_urls.len(),
+                website: website.clone(),
+            }),
+        None => {
+            if opensearch_urls.len() > 1 && !quiet {
+                eprintln!(
+                    "note: {} links {} OpenSearch descriptions; using the first, pass --select to choose another",
+                    website,
+                    opensearch_urls.len()
+                );
             }
+
+            Ok(opensearch_urls[0].clone())
+        }
+    }
+}
+
+/// A --verbose line reporting how long `phase` has taken since `start`.
+fn timing_note(args: &Args, phase: &str, start: Instant) -> Option<String> {
+    args.verbose
+        .then(|| format!("Timing: {} took {:.2?}", phase, start.elapsed()))
+}
+
+/// Every OpenSearch description the website links, in document order.
+async fn discover_opensearch_urls(
+    fetcher: &Fetcher,
+    website: &Url,
+    args: &Args,
+) -> Result<Vec<Url>, Error> {
+    if args.verbose {
+        eprintln!("Fetching HTML page: {}", website);
+    }
+
+    let start = Instant::now();
+    let (page_url, webpage_raw) = get_webpage_raw(fetcher, website.clone()).await?;
+
+    if let Some(note) = timing_note(args, "HTML fetch", start) {
+        eprintln!("{}", note);
+    }
+
+    if args.verbose {
+        if page_url != *website {
+            eprintln!("Resolved {} to {}", website, page_url);
         }
 
-        Self {
-            short_name: short_name.into_inner().unwrap_or_default(),
-            description: description.into_inner().unwrap_or_default(),
-            images,
-            urls,
+        eprintln!("Received webpage; parsing...");
+    }
+
+    let webpage = parse_webpage(webpage_raw);
+    let matcher = LinkMatcher {
+        rel: args.rel.clone(),
+        link_type: args.link_type.clone(),
+    };
+
+    let selected = if args.lenient_discovery {
+        select_opensearch_urls_lenient_with(&webpage, &page_url, &matcher)
+    } else {
+        select_opensearch_urls_with(&webpage, &page_url, &matcher)
+    };
+
+    match selected {
+        Err(nix_opensearch_generator::Error::NoDescription(_))
+            if looks_script_rendered(&webpage) =>
+        {
+            Err(Error::ScriptRendered(website.clone()))
         }
+        selected => Ok(selected?),
     }
 }
 
-#[serde_as]
-#[derive(Debug, Deserialize, Clone)]
-#[serde(rename_all = "kebab-case")]
-struct OpenSearchUrl {
-    #[serde_as(as = "DisplayFromStr")]
-    #[serde(rename = "type")]
-    template_type: Mime,
-    template: Url,
-}
-
-impl OpenSearchUrl {
-    fn into_nix(&self, buf: &mut String) {
-        let mut queryless_template = self.template.clone();
-        queryless_template.set_query(None);
-
-        *buf += "        {\n";
-        *buf += &format!("            template = \"{}\";\n", queryless_template);
-        *buf += &format!("            type = \"{}\";\n", self.template_type);
-
-        if self.template.query().is_some() {
-            *buf += "            params = [\n";
-
-            for (parameter_key, parameter_value) in self.template.query_pairs() {
-                *buf += "                {\n";
-                *buf += &format!("                    name = \"{}\";\n", parameter_key);
-                *buf += &format!("                    value = \"{}\";\n", parameter_value);
-                *buf += "                }\n";
+/// Fetches the first of the candidate urls that serves something parsable as an OpenSearch
+/// description, for links --lenient-discovery found without the OpenSearch type.
+async fn fetch_first_opensearch_raw(
+    fetcher: &Fetcher,
+    website: &Url,
+    candidates: &[Url],
+    args: &Args,
+) -> Result<(Url, String), Error> {
+    for candidate in candidates {
+        let verified = match get_opensearch_raw(fetcher, candidate.clone()).await {
+            Ok(raw) => parse_opensearch_with_base(&raw, candidate).map(|_| raw),
+            Err(error) => Err(error),
+        };
+
+        match verified {
+            Ok(raw) => {
+                if args.verbose {
+                    eprintln!("Using opensearch url: {}", candidate);
+                }
+
+                return Ok((candidate.clone(), raw));
+            }
+            Err(error) if args.verbose => {
+                eprintln!(
+                    "Skipping {}: not an OpenSearch description ({})",
+                    candidate, error
+                )
             }
+            Err(_) => (),
+        }
+    }
+
+    Err(nix_opensearch_generator::Error::NoDescription(website.clone()).into())
+}
+
+async fn fetch_opensearch_raw(
+    fetcher: &Fetcher,
+    website: &Url,
+    args: &Args,
+) -> Result<(Url, String), Error> {
+    let opensearch_urls = discover_opensearch_urls(fetcher, website, args).await?;
+
+    if args.lenient_discovery && args.select.is_none() {
+        return fetch_first_opensearch_raw(fetcher, website, &opensearch_urls, args).await;
+    }
+
+    let opensearch_url = choose_opensearch_url(&opensearch_urls, website, args.select, args.quiet)?;
 
-            *buf += "            ];\n";
+    if args.verbose {
+        if opensearch_urls.len() > 1 {
+            eprintln!("Found {} opensearch urls:", opensearch_urls.len());
+
+            for (index, url) in opensearch_urls.iter().enumerate() {
+                eprintln!("    {}: {}", index, url);
+            }
         }
 
-        *buf += "        }\n";
+        eprintln!("Using opensearch url: {}", opensearch_url);
+    }
+
+    let start = Instant::now();
+    let opensearch_raw = get_opensearch_raw(fetcher, opensearch_url.clone()).await?;
+
+    if let Some(note) = timing_note(args, "descriptor fetch", start) {
+        eprintln!("{}", note);
     }
+
+    Ok((opensearch_url, opensearch_raw))
 }
 
-#[serde_as]
-#[derive(Debug, Deserialize, Clone, Eq, PartialEq)]
-#[serde(rename_all = "kebab-case")]
-struct OpenSearchImage {
-    #[serde_as(as = "DisplayFromStr")]
-    #[serde(rename = "type")]
-    image_type: Mime,
-    width: Option<u16>,
-    height: Option<u16>,
-    #[serde(rename = "$value")]
-    url: Url,
+/// Whether parsing failed only because a url was relative and there was nothing to resolve it
+/// against.
+fn is_relative_url_error(error: &nix_opensearch_generator::Error) -> bool {
+    use nix_opensearch_generator::Error::{InvalidImageUrl, InvalidTemplateUrl};
+
+    matches!(
+        error,
+        InvalidImageUrl { source, .. } | InvalidTemplateUrl { source, .. }
+            if *source == url::ParseError::RelativeUrlWithoutBase
+    )
 }
 
-impl OpenSearchImage {
-    fn into_nix(&self, buf: &mut String) {
-        *buf += &format!("    iconUpdateURL = \"{}\";\n", self.url);
+/// A note for descriptions that can't offer search suggestions, naming the url types they have.
+fn missing_suggestions_note(source: &str, opensearch: &OpenSearchDescription) -> Option<String> {
+    if opensearch.urls.iter().any(OpenSearchUrl::is_suggestions) {
+        return None;
     }
+
+    let found = opensearch
+        .urls
+        .iter()
+        .map(|url| url.template_type.essence_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Some(format!(
+        "note: {} has no suggestions url (found: {})",
+        source, found
+    ))
+}
+
+/// A note for a url whose `{startIndex}` or `{startPage}` don't count from 1, which the Nix
+/// config can't express and may need adjusting by hand.
+fn offset_note(source: &str, url: &OpenSearchUrl) -> Option<String> {
+    (!url.has_default_offsets()).then(|| {
+        format!(
+            "note: {}: {} counts startIndex from {} and startPage from {}",
+            source,
+            url.full_template(),
+            url.index_offset,
+            url.page_offset
+        )
+    })
+}
+
+/// A note for a url that asks for `{count}` results, which the Nix config keeps as-is for the
+/// browser to fill in with its own page size.
+fn count_note(source: &str, url: &OpenSearchUrl) -> Option<String> {
+    url.uses_placeholder(COUNT_PLACEHOLDER).then(|| {
+        format!(
+            "note: {}: {} uses {{{}}}, left for the browser to fill with its page size",
+            source,
+            url.full_template(),
+            COUNT_PLACEHOLDER
+        )
+    })
 }
 
-impl Ord for OpenSearchImage {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        if self.image_type == other.image_type {
-            let width = self.width.unwrap_or_default();
-            let height = self.height.unwrap_or_default();
-            let other_width = other.width.unwrap_or_default();
-            let other_height = other.height.unwrap_or_default();
-            let size_order = (other_width * other_height).cmp(&(width * height));
+/// A warning for a results url whose template has placeholders Firefox will send literally.
+fn placeholder_warning(url: &OpenSearchUrl) -> Option<String> {
+    let placeholders = url.unsubstituted_placeholders();
 
-            size_order
-        } else {
-            std::cmp::Ordering::Equal
+    (url.is_results() && !placeholders.is_empty()).then(|| {
+        format!(
+            "{} uses {{{}}}, which Firefox won't substitute",
+            url.full_template(),
+            placeholders.join("}, {")
+        )
+    })
+}
+
+/// Describes a description for `--check`, returning whether it is usable.
+fn check_summary(
+    source: &str,
+    opensearch: &OpenSearchDescription,
+    allowed_schemes: &[String],
+    summary: &mut String,
+) -> bool {
+    let mut passed = true;
+
+    *summary += &format!("{}: {:?}\n", source, opensearch.short_name);
+
+    for url in &opensearch.urls {
+        *summary += &format!(
+            "    url: {} (type: {}, rel: {})\n",
+            url.template,
+            url.template_type.essence_str(),
+            url.rel
+        );
+    }
+
+    if opensearch.short_name.trim().is_empty() {
+        *summary += "    error: missing ShortName\n";
+        passed = false;
+    }
+
+    if let Err(error) = opensearch.validate() {
+        *summary += &format!("    error: {}\n", error);
+        passed = false;
+    }
+
+    if let Err(error) = opensearch.validate_schemes(allowed_schemes) {
+        *summary += &format!("    error: {}\n", error);
+        passed = false;
+    }
+
+    // A blank ShortName is already reported as an error above.
+    if let Err(error @ nix_opensearch_generator::Error::ShortNameTooLong { .. }) =
+        opensearch.validate_short_name()
+    {
+        *summary += &format!("    warning: {}\n", error);
+    }
+
+    for warning in &opensearch.warnings {
+        *summary += &format!("    warning: {}\n", warning);
+    }
+
+    for field in opensearch.missing_recommended_fields() {
+        *summary += &format!("    warning: missing recommended {}\n", field);
+    }
+
+    *summary += if passed { "    ok\n" } else { "    failed\n" };
+
+    passed
+}
+
+/// Runs a test query against each of the description's urls for `--validate-live`, returning
+/// how many were queried and how many of those didn't succeed.
+async fn validate_live(
+    fetcher: &Fetcher,
+    source: &str,
+    opensearch: &OpenSearchDescription,
+    report: &mut String,
+) -> (usize, usize) {
+    let search_terms = opensearch
+        .example_search_terms()
+        .unwrap_or(DEFAULT_LIVE_SEARCH_TERMS);
+    let (mut total, mut failed) = (0, 0);
+
+    *report += &format!("{}: live query for {:?}\n", source, search_terms);
+
+    for url in &opensearch.urls {
+        if url.rel == OpenSearchRel::SelfLink {
+            continue;
+        }
+
+        if url.method != OpenSearchMethod::Get {
+            *report += &format!("    skipped: {} {}\n", url.method, url.full_template());
+            continue;
+        }
+
+        total += 1;
+
+        let result = match url.query_url(search_terms) {
+            Ok(query_url) => get_query_status(fetcher, query_url.clone())
+                .await
+                .map(|status| (query_url, status)),
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok((query_url, status)) if status.is_success() => {
+                *report += &format!("    ok: {} {}\n", status, query_url);
+            }
+            Ok((query_url, status)) => {
+                *report += &format!("    failed: {} {}\n", status, query_url);
+                failed += 1;
+            }
+            Err(error) => {
+                *report += &format!("    failed: {}\n", error);
+                failed += 1;
+            }
         }
     }
+
+    (total, failed)
+}
+
+/// A unified diff from the `existing` output to the `generated` one, if they differ. With
+/// `ignore_whitespace`, lines are compared with their indentation and runs of whitespace
+/// collapsed, and blank lines are skipped.
+fn unified_diff(
+    existing: &str,
+    generated: &str,
+    label: &str,
+    ignore_whitespace: bool,
+) -> Option<String> {
+    let normalize = |text: &str| {
+        if !ignore_whitespace {
+            return text.to_string();
+        }
+
+        text.lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .map(|line| line + "\n")
+            .collect()
+    };
+
+    let (existing, generated) = (normalize(existing), normalize(generated));
+
+    (existing != generated).then(|| {
+        TextDiff::from_lines(&existing, &generated)
+            .unified_diff()
+            .header(label, &format!("{} (generated)", label))
+            .to_string()
+    })
 }
 
-impl PartialOrd for OpenSearchImage {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+/// Ends the output with exactly one newline, or none without `final_newline`, whether it's
+/// printed or written to a file.
+fn finish_output(output: &str, final_newline: bool) -> String {
+    let output = output.trim_end_matches('\n');
+
+    if final_newline {
+        format!("{}\n", output)
+    } else {
+        output.to_string()
     }
 }
 
-/// Fetches a html webpage and extracts the open-search protocol information.
-#[derive(Debug, Parser)]
-#[command(version)]
-struct Args {
-    /// The website url to convert.
-    website: Url,
+fn write_output(contents: &str, path: &Path, force: bool) -> Result<(), Error> {
+    let write_error = |source: io::Error| {
+        if source.kind() == io::ErrorKind::AlreadyExists {
+            Error::OutputExists(path.to_path_buf())
+        } else {
+            Error::WriteOutput {
+                path: path.to_path_buf(),
+                source,
+            }
+        }
+    };
 
-    #[arg(long, short, action)]
-    verbose: bool,
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(force)
+        .truncate(force)
+        .create_new(!force)
+        .open(path)
+        .map_err(write_error)?;
+
+    file.write_all(contents.as_bytes()).map_err(write_error)
 }
 
-async fn get_webpage_raw(url: Url) -> String {
-    reqwest::get(url)
-        .await
-        .expect("Failed to send get request to webpage")
-        .text()
-        .await
-        .expect("Failed to get text response from webpage")
+/// A parsed engine along with how it should be emitted.
+#[derive(Debug)]
+struct Engine {
+    /// Where the description came from, for error reporting.
+    source: String,
+    /// The description document's own url or path, for --annotate.
+    descriptor: String,
+    attr_name: String,
+    aliases: Vec<String>,
+    /// The preferred icon encoded as a data uri, when inlining icons.
+    inline_icon: Option<String>,
+    opensearch: OpenSearchDescription,
 }
 
-fn parse_webpage(webpage_raw: impl AsRef<str>) -> Html {
-    Html::parse_document(webpage_raw.as_ref())
+/// Derives an alias like `@duckduckgo` from the short name, if it has any usable characters.
+fn default_alias(short_name: &str) -> Option<String> {
+    let slug = slugify(short_name);
+    (!slug.is_empty()).then(|| format!("@{}", slug))
 }
 
-fn select_opensearch_url(document: &Html, current_url: &Url) -> Url {
-    let root = document.root_element();
+/// Drops repeated aliases, keeping the first of each, and prefixes any without one with `@`.
+fn normalize_aliases(aliases: &[String], prefix: bool) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(aliases.len());
 
-    let mut url = None;
+    for alias in aliases.iter().map(|alias| alias.trim()) {
+        if alias.is_empty() {
+            continue;
+        }
 
-    'root: for root_child in root.child_elements() {
-        if root_child.value().name() == "head" {
-            for head_child in root_child.child_elements() {
-                let head_child_element = head_child.value();
-                if head_child_element
-                    .attr("rel")
-                    .map(|attr| attr == META_TAG_REL)
-                    .unwrap_or_default()
-                {
-                    if head_child_element
-                        .attr("type")
-                        .map(|attr| attr == META_TAG_TYPE)
-                        .unwrap_or_default()
-                    {
-                        let url_raw = head_child_element
-                            .attr("href")
-                            .expect("Failed to get opensearch url from meta tag");
-                        url = Some(
-                            current_url
-                                .join(url_raw)
-                                .expect("Incorrectly formatted opensearch url"),
-                        );
-                        break 'root;
-                    }
-                }
-            }
+        let alias = if prefix && !alias.starts_with('@') {
+            format!("@{}", alias)
+        } else {
+            alias.to_string()
+        };
+
+        if !normalized.contains(&alias) {
+            normalized.push(alias);
         }
     }
 
-    url.expect("Failed to locate opensearch meta tag in webpage")
+    normalized
 }
 
-async fn get_opensearch_raw(url: Url) -> String {
-    reqwest::get(url)
-        .await
-        .expect("Failed to send opensearch get request")
-        .text()
-        .await
-        .expect("Failed to get opensearch file")
+fn check_unique_attr_names(engines: &[Engine]) -> Result<(), Error> {
+    for (index, engine) in engines.iter().enumerate() {
+        if let Some(first) = engines[..index]
+            .iter()
+            .find(|other| other.attr_name == engine.attr_name)
+        {
+            return Err(Error::DuplicateAttrName {
+                attr_name: engine.attr_name.clone(),
+                first: first.source.clone(),
+                second: engine.source.clone(),
+            });
+        }
+    }
+
+    Ok(())
 }
 
-fn deserialize_opensearch_xml(xml: impl AsRef<str>) -> OpenSearchDescription {
-    serde_xml_rs::from_str(xml.as_ref()).expect("Failed to deserialize opensearch xml data")
+/// Wraps several engine attributes into a single attrset.
+fn combine_nix_entries(entries: &[String], shape: OutputShape, indent: IndentStyle) -> String {
+    let (open, close) = match shape {
+        OutputShape::Attrset => ("{", "}"),
+        OutputShape::List => ("[", "]"),
+    };
+    let mut nix = format!("{}\n", open);
+
+    for entry in entries {
+        for line in entry.lines() {
+            if !line.is_empty() {
+                nix += &indent.level(1);
+                nix += line;
+            }
+
+            nix += "\n";
+        }
+    }
+
+    nix += close;
+    nix
 }
 
-// Single threaded since multithreading would have no gain.
-#[tokio::main(flavor = "current_thread")]
-async fn main() {
-    let args = Args::parse();
+/// Wraps an attrset of engines into an importable module that assigns it to `module_path`.
+fn nix_module(attrset: &str, module_path: &str, indent: IndentStyle) -> String {
+    let mut nix = format!(
+        "{{ config, ... }}:\n{{\n{}{} = ",
+        indent.level(1),
+        module_path
+    );
 
-    if args.verbose {
-        println!("Fetching HTML page: {}", args.website);
+    for (index, line) in attrset.lines().enumerate() {
+        if index > 0 {
+            nix += "\n";
+
+            if !line.is_empty() {
+                nix += &indent.level(1);
+            }
+        }
+
+        nix += line;
     }
 
-    let webpage_raw = get_webpage_raw(args.website.clone()).await;
+    nix += ";\n}";
+    nix
+}
+
+/// Comments recording where an engine was generated from, for --annotate.
+fn provenance_comment(engine: &Engine) -> String {
+    // Comments end at a newline, so one in a file name must not escape them.
+    let single_line = |value: &str| value.replace(['\n', '\r'], " ");
+    let mut comment = format!("# Generated from {}\n", single_line(&engine.descriptor));
 
-    if args.verbose {
-        println!("Received webpage; parsing...");
+    if let Some(search_form) = &engine.opensearch.search_form {
+        comment += &format!("# Search form: {}\n", search_form);
     }
 
-    let webpage = parse_webpage(webpage_raw);
-    let opensearch_url = select_opensearch_url(&webpage, &args.website);
+    comment
+}
 
-    if args.verbose {
-        println!("Found opensearch url: {}", opensearch_url);
+/// The engine's homepage: its search form, else the origin of the description or, failing
+/// that, of its results url.
+fn homepage(engine: &Engine) -> Option<Url> {
+    if let Some(search_form) = &engine.opensearch.search_form {
+        return Some(search_form.clone());
     }
 
-    let opensearch_raw = get_opensearch_raw(opensearch_url).await;
+    Url::parse(&engine.descriptor)
+        .ok()
+        .into_iter()
+        .chain(
+            engine
+                .opensearch
+                .urls
+                .iter()
+                .filter(|url| url.is_results())
+                .map(|url| url.template.clone()),
+        )
+        .find(|url| matches!(url.scheme(), "http" | "https"))
+        .and_then(|url| url.join("/").ok())
+}
+
+/// Emits the engines as Nix, each shaped by `options` along with its own name, aliases and icon.
+fn nix_output(
+    engines: &[Engine],
+    options: &NixOptions,
+    module_path: Option<&str>,
+    annotate: bool,
+    include_homepage: bool,
+    shape: OutputShape,
+) -> Result<String, Error> {
+    let indent = options.indent;
+    let entries = engines
+        .iter()
+        .map(|engine| {
+            let mut nix = String::new();
 
-    if args.verbose {
-        println!("Received opensearch file; parsing...");
+            if include_homepage {
+                if let Some(homepage) = homepage(engine) {
+                    nix += &format!("# homepage: {}\n", homepage);
+                }
+            }
+
+            if annotate {
+                nix += &provenance_comment(engine);
+            }
+
+            let engine_options = NixOptions {
+                attr_name: Some(engine.attr_name.clone()),
+                list_item: shape == OutputShape::List,
+                aliases: engine.aliases.clone(),
+                inline_icon: engine.inline_icon.clone(),
+                ..options.clone()
+            };
+
+            engine.opensearch.into_nix(&engine_options, &mut nix)?;
+            Ok(nix)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(match (entries.as_slice(), module_path, shape) {
+        (entries, Some(module_path), shape) => nix_module(
+            &combine_nix_entries(entries, shape, indent),
+            module_path,
+            indent,
+        ),
+        ([entry], None, OutputShape::Attrset) => entry.clone(),
+        (entries, None, shape) => combine_nix_entries(entries, shape, indent),
+    })
+}
+
+/// Emits a single engine as an object, or several as an object keyed by attribute name.
+fn json_output(engines: &[Engine]) -> Result<String, Error> {
+    match engines {
+        [engine] => Ok(serde_json::to_string_pretty(
+            &engine.opensearch.to_output(),
+        )?),
+        engines => Ok(serde_json::to_string_pretty(&engine_outputs(engines))?),
+    }
+}
+
+/// Emits the engines in the same shape as [`json_output`], as yaml.
+fn yaml_output(engines: &[Engine]) -> Result<String, Error> {
+    match engines {
+        [engine] => Ok(serde_yaml::to_string(&engine.opensearch.to_output())?),
+        engines => Ok(serde_yaml::to_string(&engine_outputs(engines))?),
     }
+}
+
+/// Several engines' outputs keyed by attribute name.
+fn engine_outputs(engines: &[Engine]) -> BTreeMap<&str, EngineOutput> {
+    engines
+        .iter()
+        .map(|engine| (engine.attr_name.as_str(), engine.opensearch.to_output()))
+        .collect()
+}
+
+/// Emits the engines as a complete Firefox `policies.json`.
+fn firefox_policy_output(engines: &[Engine]) -> Result<String, Error> {
+    let entries = engines
+        .iter()
+        .map(|engine| {
+            engine.opensearch.validate()?;
 
-    let opensearch = deserialize_opensearch_xml(opensearch_raw);
+            Ok(engine.opensearch.to_firefox_policy(&engine.aliases))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
 
+    Ok(serde_json::to_string_pretty(&serde_json::json!({
+        "policies": {
+            "SearchEngines": {
+                "Add": entries,
+            },
+        },
+    }))?)
+}
+
+/// Parses a description and applies the url ordering and fallbacks the arguments ask for.
+fn parse_description(
+    args: &Args,
+    source: &str,
+    base: Option<&Url>,
+    opensearch_raw: &str,
+) -> Result<OpenSearchDescription, Error> {
     if args.verbose {
-        println!("Serializing into Nix...");
+        eprintln!("Received opensearch file from {}; parsing...", source);
     }
 
-    let mut nix = String::new();
-    opensearch.into_nix(&mut nix);
+    let start = Instant::now();
+    let parsed = if args.skip_invalid {
+        parse_opensearch_skipping_invalid(opensearch_raw, base)
+    } else {
+        match base {
+            Some(base) => parse_opensearch_with_base(opensearch_raw, base),
+            None => parse_opensearch(opensearch_raw),
+        }
+    };
 
-    println!("{}", nix);
-}
+    if let Some(note) = timing_note(args, "parse", start) {
+        eprintln!("{}", note);
+    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    let mut opensearch = parsed.map_err(|error| {
+        if base.is_none() && is_relative_url_error(&error) {
+            Error::MissingBaseUrl {
+                input: source.to_string(),
+                error: Box::new(error),
+            }
+        } else {
+            error.into()
+        }
+    })?;
 
-    #[test]
-    fn deserialize_xml() {
-        let raw = r#"<?xml version="1.0"?>
+    if args.verbose {
+        for warning in &opensearch.warnings {
+            eprintln!("warning: {}: {}", source, warning);
+        }
+
+        if let Some(note) = missing_suggestions_note(source, &opensearch) {
+            eprintln!("{}", note);
+        }
+
+        for note in opensearch.urls.iter().flat_map(|url| {
+            [offset_note(source, url), count_note(source, url)]
+                .into_iter()
+                .flatten()
+        }) {
+            eprintln!("{}", note);
+        }
+    }
+
+    if !args.url_type.is_empty() {
+        opensearch.retain_url_types(&args.url_type)?;
+    }
+
+    if !args.keep_duplicates {
+        opensearch.dedupe_urls();
+    }
+
+    if !args.preserve_order {
+        opensearch.sort_urls();
+    }
+
+    if args.fallback_favicon {
+        match base {
+            Some(base) => opensearch.add_fallback_favicon(base),
+            None if opensearch.images.is_empty() && !args.quiet => eprintln!(
+                "warning: {} has no url to find a fallback favicon at",
+                source
+            ),
+            None => (),
+        }
+    }
+
+    if let Some(locale) = &args.substitute_locale {
+        for url in &mut opensearch.urls {
+            url.substitute_placeholder(LANGUAGE_PLACEHOLDER, locale)?;
+        }
+    }
+
+    if args.debug_parsed {
+        eprintln!("Parsed {}: {:#?}", source, opensearch);
+    }
+
+    Ok(opensearch)
+}
+
+/// A name for an engine without a short name, from the host of the descriptor it came from, so
+/// its attribute name isn't empty.
+fn fallback_short_name(descriptor: &str) -> Option<String> {
+    Url::parse(descriptor)
+        .ok()?
+        .host_str()
+        .map(|host| host.trim_start_matches("www.").to_string())
+}
+
+/// Fetches the preferred icon as a data uri for --inline-icon. An icon that fails to fetch, or is
+/// over --max-icon-size, gives way to the next preferred one, then to iconUpdateURL if none works.
+async fn fetch_inline_icon(
+    args: &Args,
+    fetcher: &Fetcher,
+    images: &[OpenSearchImage],
+) -> Option<String> {
+    let preferred = OpenSearchDescription::select_icon(images)?;
+
+    if !args.inline_icon || preferred.is_data_uri() {
+        return None;
+    }
+
+    let mut candidates = images
+        .iter()
+        .filter(|image| !image.is_data_uri())
+        .collect::<Vec<_>>();
+    candidates.sort();
+
+    for image in candidates {
+        if args.verbose {
+            eprintln!("Inlining icon: {}", image.url);
+        }
+
+        match get_icon_data_uri(fetcher, image, args.max_icon_size).await {
+            Ok(data_uri) => return Some(data_uri),
+            Err(error) => {
+                if !args.quiet {
+                    eprintln!("warning: {}", error);
+                }
+            }
+        }
+    }
+
+    if !args.quiet {
+        eprintln!("warning: no icon could be inlined; falling back to iconUpdateURL");
+    }
+
+    None
+}
+
+/// Validates a parsed description and names it as the arguments ask.
+async fn build_engine(
+    args: &Args,
+    fetcher: &Fetcher,
+    source: String,
+    descriptor: String,
+    mut opensearch: OpenSearchDescription,
+) -> Result<Engine, Error> {
+    opensearch.validate_schemes(&args.allow_scheme)?;
+
+    if let Err(error) = opensearch.validate_short_name() {
+        if args.strict {
+            return Err(error.into());
+        }
+
+        if !args.quiet {
+            eprintln!("warning: {}", error);
+        }
+    }
+
+    if opensearch.short_name.trim().is_empty() {
+        let host = fallback_short_name(&descriptor).ok_or_else(|| Error::NoFallbackName {
+            descriptor: descriptor.clone(),
+        })?;
+
+        if !args.quiet {
+            eprintln!("warning: {}: naming the engine {:?}", source, host);
+        }
+
+        opensearch.short_name = host;
+    }
+
+    if !args.quiet {
+        for warning in opensearch.urls.iter().filter_map(placeholder_warning) {
+            eprintln!("warning: {}: {}", source, warning);
+        }
+    }
+
+    if !args.quiet && args.params_shape == ParamsShapeArg::Attrset {
+        for url in &opensearch.urls {
+            let repeated = url.repeated_param_names();
+
+            if !repeated.is_empty() {
+                eprintln!(
+                    "warning: {}: {} repeats {}; only the last value is kept",
+                    source,
+                    url.full_template(),
+                    repeated.join(", ")
+                );
+            }
+        }
+    }
+
+    let attr_name = match &args.attr_name {
+        Some(attr_name) => attr_name.clone(),
+        None if args.slugify => slugify(&opensearch.short_name),
+        None => opensearch.short_name.clone(),
+    };
+
+    let aliases = if !args.alias.is_empty() {
+        normalize_aliases(&args.alias, !args.no_alias_prefix)
+    } else if args.no_default_alias {
+        Vec::new()
+    } else {
+        default_alias(&opensearch.short_name).into_iter().collect()
+    };
+
+    let inline_icon = fetch_inline_icon(args, fetcher, &opensearch.images).await;
+
+    Ok(Engine {
+        source,
+        descriptor,
+        attr_name,
+        aliases,
+        inline_icon,
+        opensearch,
+    })
+}
+
+/// Reads the website urls listed one per line, skipping blank lines and `#` comments.
+fn read_urls_file(path: &Path) -> Result<Vec<String>, Error> {
+    let urls = std::fs::read_to_string(path).map_err(|source| Error::ReadUrlsFile {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(urls
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Defaults read from a --config file, each standing in for the flag of the same name.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    user_agent: Option<String>,
+    timeout: Option<u64>,
+    indent: Option<usize>,
+    format: Option<OutputFormat>,
+    module_path: Option<String>,
+}
+
+impl Config {
+    fn read(path: &Path) -> Result<Self, Error> {
+        let raw = std::fs::read_to_string(path).map_err(|source| Error::ReadConfig {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        toml::from_str(&raw).map_err(|source| Error::ParseConfig {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Fills in the flags `matches` didn't get from the command line.
+    fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+
+        if let Some(user_agent) = self.user_agent.filter(|_| unset("user_agent")) {
+            args.user_agent = user_agent;
+        }
+
+        if let Some(timeout) = self.timeout.filter(|_| unset("timeout")) {
+            args.timeout = timeout;
+        }
+
+        if let Some(indent) = self.indent.filter(|_| unset("indent")) {
+            args.indent = indent;
+        }
+
+        if let Some(format) = self.format.filter(|_| unset("format")) {
+            args.format = format;
+        }
+
+        if let Some(module_path) = self.module_path.filter(|_| unset("module_path")) {
+            args.module_path = module_path;
+        }
+    }
+}
+
+/// Builds the arguments from `matches`, with defaults from any --config file beneath them.
+fn load_args(matches: &ArgMatches) -> Result<Args, Error> {
+    let mut args = Args::from_arg_matches(matches).unwrap_or_else(|error| error.exit());
+
+    if let Some(path) = &args.config {
+        Config::read(path)?.apply(&mut args, matches);
+    }
+
+    Ok(args)
+}
+
+/// Runs `task` until it finishes or `cancel` resolves, dropping any requests still in flight.
+async fn run_cancellable(
+    task: impl Future<Output = Result<(), Error>>,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), Error> {
+    tokio::select! {
+        result = task => result,
+        () = cancel => Err(Error::Aborted),
+    }
+}
+
+/// Resolves on Ctrl-C, or never if the signal can't be listened for.
+async fn ctrl_c() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+async fn run(args: &Args) -> Result<(), Error> {
+    let on_redirect = args.verbose.then(|| -> RedirectHook {
+        Arc::new(|from, to| eprintln!("Redirected from {} to {}", from, to))
+    });
+    let fetcher = Fetcher::with_config(FetcherConfig {
+        timeout: Duration::from_secs(args.timeout),
+        retries: args.retries,
+        max_redirects: args.max_redirects,
+        user_agent: args.user_agent.clone(),
+        headers: args.headers.iter().cloned().collect(),
+        on_redirect,
+    })?;
+
+    // A batch from --urls-file reports each failing website and carries on with the rest.
+    let mut batch_failures = 0;
+    let mut batch_failure = |source: &str, error: Error| {
+        if args.urls_file.is_none() {
+            return Err(error);
+        }
+
+        if !args.quiet {
+            eprintln!("error: {}: {}", source, error);
+        }
+
+        batch_failures += 1;
+        Ok(())
+    };
+
+    if args.print_descriptor_url {
+        let fetcher = &fetcher;
+        let discoveries = args
+            .website
+            .iter()
+            .map(|website| discover_opensearch_urls(fetcher, website, args));
+
+        for opensearch_url in try_join_all(discoveries).await?.into_iter().flatten() {
+            println!("{}", opensearch_url);
+        }
+
+        return Ok(());
+    }
+
+    // Each description is paired with where it came from, for error reporting, and the url it
+    // was fetched from, for resolving relative urls.
+    let opensearch_raws = if let Some(path) = &args.input_file {
+        if args.verbose {
+            eprintln!("Reading opensearch file: {}", path.display());
+        }
+
+        vec![(
+            path.display().to_string(),
+            args.base_url.clone(),
+            read_opensearch_file(path)?,
+        )]
+    } else if let Some(descriptor_url) = &args.descriptor_url {
+        if args.verbose {
+            eprintln!("Fetching opensearch file: {}", descriptor_url);
+        }
+
+        let start = Instant::now();
+        let opensearch_raw = get_opensearch_raw(&fetcher, descriptor_url.clone()).await?;
+
+        if let Some(note) = timing_note(args, "descriptor fetch", start) {
+            eprintln!("{}", note);
+        }
+
+        vec![(
+            descriptor_url.to_string(),
+            Some(descriptor_url.clone()),
+            opensearch_raw,
+        )]
+    } else if let Some(path) = &args.urls_file {
+        let fetcher = &fetcher;
+        let fetches = read_urls_file(path)?.into_iter().map(|website| async move {
+            let fetched = match Url::parse(&website) {
+                Ok(url) => fetch_opensearch_raw(fetcher, &url, args).await,
+                Err(source) => Err(Error::InvalidWebsite {
+                    website: website.clone(),
+                    source,
+                }),
+            };
+
+            (website, fetched)
+        });
+
+        let mut opensearch_raws = Vec::new();
+
+        for (website, fetched) in join_all(fetches).await {
+            match fetched {
+                Ok((opensearch_url, opensearch_raw)) => {
+                    opensearch_raws.push((website, Some(opensearch_url), opensearch_raw))
+                }
+                Err(error) => batch_failure(&website, error)?,
+            }
+        }
+
+        opensearch_raws
+    } else {
+        let fetcher = &fetcher;
+        let fetches = args.website.iter().map(|website| async move {
+            let (opensearch_url, opensearch_raw) =
+                fetch_opensearch_raw(fetcher, website, args).await?;
+            Ok::<_, Error>((website.to_string(), Some(opensearch_url), opensearch_raw))
+        });
+
+        try_join_all(fetches).await?
+    };
+
+    let opensearch_raws_len = opensearch_raws.len();
+    let mut engines = Vec::with_capacity(opensearch_raws_len);
+    let mut check_failures = 0;
+    let mut check_report = String::new();
+    let (mut live_total, mut live_failures) = (0, 0);
+
+    for (source, base, opensearch_raw) in opensearch_raws {
+        let opensearch = match parse_description(args, &source, base.as_ref(), &opensearch_raw) {
+            Ok(opensearch) => opensearch,
+            Err(error) => {
+                batch_failure(&source, error)?;
+                continue;
+            }
+        };
+
+        if args.validate_live {
+            let mut live_report = String::new();
+            let (total, failed) =
+                validate_live(&fetcher, &source, &opensearch, &mut live_report).await;
+
+            if !args.quiet {
+                eprint!("{}", live_report);
+            }
+
+            live_total += total;
+            live_failures += failed;
+        }
+
+        if args.check {
+            if !check_summary(&source, &opensearch, &args.allow_scheme, &mut check_report) {
+                check_failures += 1;
+            }
+
+            continue;
+        }
+
+        // Prefer the url the description was actually fetched from over the one it claims.
+        let descriptor = match (&base, opensearch.self_url()) {
+            (Some(base), _) | (None, Some(base)) => base.to_string(),
+            (None, None) => source.clone(),
+        };
+
+        match build_engine(args, &fetcher, source.clone(), descriptor, opensearch).await {
+            Ok(engine) => engines.push(engine),
+            Err(error) => batch_failure(&source, error)?,
+        }
+    }
+
+    if args.check {
+        print!("{}", check_report);
+    }
+
+    if live_failures > 0 {
+        return Err(Error::LiveQueryFailed {
+            failed: live_failures,
+            total: live_total,
+        });
+    }
+
+    if args.check {
+        if check_failures > 0 {
+            return Err(Error::CheckFailed {
+                failed: check_failures,
+                total: opensearch_raws_len,
+            });
+        }
+
+        if batch_failures > 0 {
+            return Err(Error::BatchFailed {
+                failed: batch_failures,
+            });
+        }
+
+        return Ok(());
+    }
+
+    check_unique_attr_names(&engines)?;
+
+    if args.verbose {
+        eprintln!("Serializing into {:?}...", args.format);
+    }
+
+    let output = match args.format {
+        OutputFormat::Nix => {
+            let indent = if args.tabs {
+                IndentStyle::Tabs
+            } else {
+                IndentStyle::Spaces(args.indent)
+            };
+
+            let module_path = args.module.then_some(args.module_path.as_str());
+
+            let query_style = if args.no_query_split {
+                QueryStyle::Template
+            } else if args.raw_params {
+                QueryStyle::RawParams
+            } else {
+                QueryStyle::Params
+            };
+
+            let options = NixOptions {
+                all_icons: args.all_icons,
+                icon_update_interval: args.icon_update_interval,
+                url_types: args.url_type.clone(),
+                include_feeds: args.include_feeds,
+                query_style,
+                params_shape: args.params_shape.into(),
+                sort_params: args.sort_params,
+                indent,
+                ..NixOptions::default()
+            };
+
+            let nix = nix_output(
+                &engines,
+                &options,
+                module_path,
+                args.annotate,
+                args.include_homepage,
+                args.output_shape,
+            )?;
+
+            if args.compact {
+                compact_nix(&nix)
+            } else {
+                nix
+            }
+        }
+        OutputFormat::Json => json_output(&engines)?,
+        OutputFormat::Yaml => yaml_output(&engines)?,
+        OutputFormat::FirefoxPolicy => firefox_policy_output(&engines)?,
+    };
+    let output = finish_output(&output, !args.no_final_newline);
+
+    if let Some(path) = &args.diff {
+        let existing = std::fs::read_to_string(path).map_err(|source| Error::ReadDiffFile {
+            path: path.clone(),
+            source,
+        })?;
+
+        let label = path.display().to_string();
+
+        if let Some(diff) = unified_diff(&existing, &output, &label, args.ignore_whitespace) {
+            print!("{}", diff);
+            return Err(Error::DiffFound(path.clone()));
+        }
+
+        return Ok(());
+    }
+
+    match &args.output {
+        Some(path) => {
+            write_output(&output, path, args.force)?;
+
+            if args.verbose {
+                eprintln!("Wrote output to {}", path.display());
+            }
+        }
+        None => print!("{}", output),
+    }
+
+    if batch_failures > 0 {
+        return Err(Error::BatchFailed {
+            failed: batch_failures,
+        });
+    }
+
+    Ok(())
+}
+
+/// The completion script for `shell`, covering every argument.
+fn completions(shell: Shell) -> String {
+    let mut command = Args::command();
+    let name = command.get_name().to_string();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, name, &mut script);
+
+    String::from_utf8_lossy(&script).into_owned()
+}
+
+// Single threaded since multithreading would have no gain; fetches still run concurrently.
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args = match load_args(&Args::command().get_matches()) {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.command == Some(Command::Selftest) {
+        let (output, passed) = selftest();
+        println!("{}", output);
+        println!("{}", if passed { "OK" } else { "FAIL" });
+
+        return if passed {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        };
+    }
+
+    if let Some(shell) = args.generate_completions {
+        print!("{}", completions(shell));
+        return ExitCode::SUCCESS;
+    }
+
+    match run_cancellable(run(&args), ctrl_c()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            if !args.quiet {
+                match error {
+                    Error::Aborted => eprintln!("aborted"),
+                    _ => eprintln!("error: {}", error),
+                }
+            }
+
+            ExitCode::from(exit_code(&error))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nix_opensearch_generator::OpenSearchRel;
+
+    use super::*;
+
+    #[test]
+    fn verify_args() {
+        Args::command().debug_assert();
+    }
+
+    #[tokio::test]
+    async fn cancellation_aborts() {
+        let result = run_cancellable(std::future::pending(), async {}).await;
+        assert!(matches!(result, Err(Error::Aborted)));
+
+        let result = run_cancellable(async { Ok(()) }, std::future::pending()).await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn input_file_excludes_website() {
+        assert!(Args::try_parse_from(["test", "--input-file", "engine.xml"]).is_ok());
+        assert!(Args::try_parse_from(["test", "https://example.com"]).is_ok());
+        assert!(Args::try_parse_from([
+            "test",
+            "https://example.com",
+            "--input-file",
+            "engine.xml"
+        ])
+        .is_err());
+        assert!(Args::try_parse_from(["test"]).is_err());
+    }
+
+    #[test]
+    fn input_file_from_stdin() {
+        let args = Args::try_parse_from(["test", "--input-file", "-"]).unwrap();
+
+        assert_eq!(args.input_file.as_deref(), Some(Path::new(STDIN_PATH)));
+        assert!(
+            Args::try_parse_from(["test", "https://example.com", "--input-file", "-"]).is_err()
+        );
+    }
+
+    #[test]
+    fn descriptor_url_excludes_other_sources() {
+        let args = Args::try_parse_from([
+            "test",
+            "--descriptor-url",
+            "https://duckduckgo.com/opensearch.xml",
+        ])
+        .unwrap();
+
+        assert!(args.website.is_empty());
+        assert!(args.descriptor_url.is_some());
+        assert!(Args::try_parse_from([
+            "test",
+            "https://duckduckgo.com",
+            "--descriptor-url",
+            "https://duckduckgo.com/opensearch.xml",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn completion_scripts() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let args = Args::try_parse_from(["test", "--generate-completions", &shell.to_string()])
+                .unwrap();
+            assert_eq!(args.generate_completions, Some(shell));
+
+            let script = completions(shell);
+            assert!(script.contains("output-shape"), "{shell}");
+        }
+
+        assert!(Args::try_parse_from(["test"]).is_err());
+        assert!(Args::try_parse_from([
+            "test",
+            "https://example.com",
+            "--generate-completions",
+            "bash",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn url_type_args() {
+        let args = Args::try_parse_from([
+            "test",
+            "https://example.com",
+            "--url-type",
+            "text/html",
+            "--url-type",
+            "application/x-suggestions+json",
+        ])
+        .unwrap();
+        assert_eq!(args.url_type.len(), 2);
+
+        assert!(
+            Args::try_parse_from(["test", "https://example.com", "--url-type", "html"]).is_err()
+        );
+    }
+
+    #[test]
+    fn quiet_excludes_verbose() {
+        assert!(Args::try_parse_from(["test", "https://example.com", "--quiet"]).is_ok());
+        assert!(Args::try_parse_from(["test", "https://example.com", "-q", "-v"]).is_err());
+        assert!(
+            Args::try_parse_from(["test", "https://example.com", "-q", "--debug-parsed"]).is_err()
+        );
+    }
+
+    #[test]
+    fn config_file() {
+        let path =
+            std::env::temp_dir().join(format!("opensearch-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+                user-agent = "config-agent"
+                timeout = 5
+                indent = 2
+                format = "firefox-policy"
+                module-path = "programs.firefox.search.engines"
+            "#,
+        )
+        .unwrap();
+
+        let load = |extra: &[&str]| {
+            let matches = Args::command()
+                .try_get_matches_from(
+                    [
+                        "test",
+                        "https://example.com",
+                        "--config",
+                        path.to_str().unwrap(),
+                    ]
+                    .iter()
+                    .chain(extra),
+                )
+                .unwrap();
+            load_args(&matches).unwrap()
+        };
+
+        let args = load(&[]);
+        assert_eq!(args.user_agent, "config-agent");
+        assert_eq!(args.timeout, 5);
+        assert_eq!(args.indent, 2);
+        assert_eq!(args.format, OutputFormat::FirefoxPolicy);
+        assert_eq!(args.module_path, "programs.firefox.search.engines");
+
+        let args = load(&["--timeout", "60", "--format", "json", "--indent", "4"]);
+        assert_eq!(args.timeout, 60);
+        assert_eq!(args.format, OutputFormat::Json);
+        assert_eq!(args.indent, 4);
+        assert_eq!(args.user_agent, "config-agent");
+
+        std::fs::write(&path, "timeout = 5\nretries = 2\n").unwrap();
+        let matches = Args::command()
+            .try_get_matches_from(["test", "--config", path.to_str().unwrap(), "-i", "-"])
+            .unwrap();
+        assert!(matches!(
+            load_args(&matches),
+            Err(Error::ParseConfig { .. })
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn timing_only_when_verbose() {
+        let start = Instant::now();
+
+        let verbose = Args::try_parse_from(["test", "https://example.com", "--verbose"]).unwrap();
+        let note = timing_note(&verbose, "HTML fetch", start).unwrap();
+        assert!(note.starts_with("Timing: HTML fetch took "));
+
+        let quiet = Args::try_parse_from(["test", "https://example.com"]).unwrap();
+        assert_eq!(timing_note(&quiet, "HTML fetch", start), None);
+    }
+
+    #[tokio::test]
+    async fn empty_short_name() {
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let opensearch = parse_opensearch(
+            r#"<OpenSearchDescription>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>"#,
+        )
+        .unwrap();
+        let build = |args: &[&str], descriptor: &str| {
+            let args = Args::try_parse_from(args).unwrap();
+            let fetcher = &fetcher;
+            let opensearch = opensearch.clone();
+            let descriptor = descriptor.to_string();
+            async move { build_engine(&args, fetcher, descriptor.clone(), descriptor, opensearch).await }
+        };
+
+        let engine = build(
+            &["test", "https://www.example.com", "-q"],
+            "https://www.example.com/opensearch.xml",
+        )
+        .await
+        .unwrap();
+        assert_eq!(engine.attr_name, "example.com");
+        assert!(engine
+            .opensearch
+            .to_nix()
+            .unwrap()
+            .starts_with("\"example.com\" = {"));
+
+        let error = build(
+            &["test", "https://www.example.com", "--strict"],
+            "https://www.example.com/opensearch.xml",
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "description has no ShortName to name the engine by"
+        );
+
+        let error = build(&["test", "-i", "engine.xml", "-q"], "engine.xml")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::NoFallbackName { .. }));
+    }
+
+    #[test]
+    fn selftest_matches_golden() {
+        assert_eq!(selftest(), (SELFTEST_GOLDEN.to_string(), true));
+
+        let args = Args::try_parse_from(["test", "selftest"]).unwrap();
+        assert_eq!(args.command, Some(Command::Selftest));
+        assert!(args.website.is_empty());
+    }
+
+    #[tokio::test]
+    async fn oversized_icon_falls_back() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/large.png"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "image/png")
+                    .set_body_bytes(vec![0; 2048]),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/small.png"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "image/png")
+                    .set_body_bytes(b"icon".to_vec()),
+            )
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let opensearch = parse_opensearch(&format!(
+            r#"<OpenSearchDescription>
+                <ShortName>Mock</ShortName>
+                <Image height="64" width="64" type="image/png">{uri}/large.png</Image>
+                <Image height="16" width="16" type="image/png">{uri}/small.png</Image>
+                <Url type="text/html" template="https://example.com/search?q={{searchTerms}}" />
+            </OpenSearchDescription>"#,
+            uri = server.uri()
+        ))
+        .unwrap();
+        let inline = |extra: &[&str], images: &[OpenSearchImage]| {
+            let mut args = vec!["test", "https://example.com", "--inline-icon", "-q"];
+            args.extend(extra);
+            let args = Args::try_parse_from(args).unwrap();
+            let fetcher = &fetcher;
+            let images = images.to_vec();
+            async move { fetch_inline_icon(&args, fetcher, &images).await }
+        };
+
+        let unlimited = inline(&[], &opensearch.images).await.unwrap();
+        assert!(unlimited.len() > 2048);
+
+        assert_eq!(
+            inline(&["--max-icon-size", "1024"], &opensearch.images).await,
+            Some("data:image/png;base64,aWNvbg==".to_string())
+        );
+        assert_eq!(
+            inline(&["--max-icon-size", "1024"], &opensearch.images[..1]).await,
+            None
+        );
+
+        // An icon that fails to fetch gives way to the next one too.
+        let missing = parse_opensearch(&format!(
+            r#"<OpenSearchDescription>
+                <ShortName>Mock</ShortName>
+                <Image height="128" width="128" type="image/png">{uri}/missing.png</Image>
+                <Image height="16" width="16" type="image/png">{uri}/small.png</Image>
+                <Url type="text/html" template="https://example.com/search?q={{searchTerms}}" />
+            </OpenSearchDescription>"#,
+            uri = server.uri()
+        ))
+        .unwrap();
+        assert_eq!(
+            inline(&[], &missing.images).await,
+            Some("data:image/png;base64,aWNvbg==".to_string())
+        );
+    }
+
+    #[test]
+    fn timeout_and_retries() {
+        let args = Args::try_parse_from(["test", "https://example.com"]).unwrap();
+        assert_eq!(args.timeout, 30);
+        assert_eq!(args.retries, 2);
+
+        let args = Args::try_parse_from([
+            "test",
+            "https://example.com",
+            "--timeout",
+            "5",
+            "--retries",
+            "0",
+        ])
+        .unwrap();
+        assert_eq!(args.timeout, 5);
+        assert_eq!(args.retries, 0);
+    }
+
+    #[test]
+    fn multiple_websites() {
+        let args = Args::try_parse_from(["test", "https://a.example.com", "https://b.example.com"])
+            .unwrap();
+
+        assert_eq!(args.website.len(), 2);
+        assert!(Args::try_parse_from([
+            "test",
+            "https://a.example.com",
+            "https://b.example.com",
+            "--input-file",
+            "engine.xml",
+        ])
+        .is_err());
+    }
+
+    fn engine(source: &str, short_name: &str) -> Engine {
+        let raw = format!(
+            r#"<OpenSearchDescription>
+                <ShortName>{}</ShortName>
+                <Url type="text/html" template="https://example.com/search" />
+            </OpenSearchDescription>"#,
+            short_name
+        );
+
+        Engine {
+            source: source.to_string(),
+            descriptor: source.to_string(),
+            attr_name: short_name.to_string(),
+            aliases: Vec::new(),
+            inline_icon: None,
+            opensearch: parse_opensearch(&raw).unwrap(),
+        }
+    }
+
+    #[test]
+    fn default_aliases() {
+        assert_eq!(default_alias("DuckDuckGo").as_deref(), Some("@duckduckgo"));
+        assert_eq!(
+            default_alias("Nix Packages").as_deref(),
+            Some("@nix-packages")
+        );
+        assert_eq!(default_alias("!!!"), None);
+        assert!(Args::try_parse_from([
+            "test",
+            "https://example.com",
+            "--alias",
+            "@ddg",
+            "--no-default-alias",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn count_notes() {
+        let mut url = engine("https://a.example.com/", "A")
+            .opensearch
+            .urls
+            .remove(0);
+        assert_eq!(count_note("a.xml", &url), None);
+
+        url.template = Url::parse("https://a.example.com/search?n={count}").unwrap();
+        assert_eq!(
+            count_note("a.xml", &url).as_deref(),
+            Some(
+                "note: a.xml: https://a.example.com/search?n={count} uses {count}, left for the \
+                browser to fill with its page size"
+            )
+        );
+    }
+
+    #[test]
+    fn offset_notes() {
+        let mut url = engine("https://a.example.com/", "A")
+            .opensearch
+            .urls
+            .remove(0);
+        assert_eq!(offset_note("a.xml", &url), None);
+
+        url.index_offset = 0;
+        assert_eq!(
+            offset_note("a.xml", &url).as_deref(),
+            Some(format!(
+                "note: a.xml: {} counts startIndex from 0 and startPage from 1",
+                url.full_template()
+            ))
+            .as_deref()
+        );
+    }
+
+    #[test]
+    fn placeholder_warnings() {
+        let mut url = engine("https://a.example.com/", "A")
+            .opensearch
+            .urls
+            .remove(0);
+        assert_eq!(placeholder_warning(&url), None);
+
+        url.params.push(nix_opensearch_generator::OpenSearchParam {
+            name: "hl".to_string(),
+            value: "{language}".to_string(),
+        });
+        assert_eq!(
+            placeholder_warning(&url),
+            Some(format!(
+                "{} uses {{language}}, which Firefox won't substitute",
+                url.full_template()
+            ))
+        );
+
+        url.substitute_placeholder(LANGUAGE_PLACEHOLDER, "en-US")
+            .unwrap();
+        assert_eq!(placeholder_warning(&url), None);
+    }
+
+    #[test]
+    fn normalized_aliases() {
+        let aliases = ["ddg", "@duck", "@ddg", " duck ", "", "@d"].map(String::from);
+
+        assert_eq!(normalize_aliases(&aliases, true), ["@ddg", "@duck", "@d"]);
+        assert_eq!(
+            normalize_aliases(&aliases, false),
+            ["ddg", "@duck", "@ddg", "duck", "@d"]
+        );
+        assert!(normalize_aliases(&[], true).is_empty());
+    }
+
+    #[test]
+    fn duplicate_attr_names() {
+        let engines = [
+            engine("https://a.example.com/", "A"),
+            engine("https://b.example.com/", "B"),
+            engine("https://c.example.com/", "A"),
+        ];
+
+        assert!(check_unique_attr_names(&engines[..2]).is_ok());
+        assert_eq!(
+            check_unique_attr_names(&engines).unwrap_err().to_string(),
+            "https://a.example.com/ and https://c.example.com/ both resolve to the attribute name \"A\""
+        );
+    }
+
+    #[test]
+    fn combined_entries() {
+        let entries = [
+            "\"A\" = {\n    a = 1;\n};".to_string(),
+            "\"B\" = {\n};".to_string(),
+        ];
+
+        assert_eq!(
+            combine_nix_entries(&entries, OutputShape::Attrset, IndentStyle::default()),
+            "{\n    \"A\" = {\n        a = 1;\n    };\n    \"B\" = {\n    };\n}"
+        );
+
+        // Line breaks inside strings are escaped, so re-indenting leaves descriptions untouched.
+        let mut multi_line = engine("https://a.example.com/", "A");
+        multi_line.opensearch.description = "line one\nline two".to_string();
+        let nix = nix_output(
+            &[multi_line, engine("https://b.example.com/", "B")],
+            &NixOptions::default(),
+            None,
+            false,
+            false,
+            OutputShape::Attrset,
+        )
+        .unwrap();
+
+        assert!(nix.contains(r#"description = "line one\nline two";"#));
+        assert!(rnix::Root::parse(&nix).ok().is_ok());
+    }
+
+    #[test]
+    fn output_shapes() {
+        let engines = [
+            engine("https://a.example.com/", "A"),
+            engine("https://b.example.com/", "B"),
+        ];
+        let shaped = |shape| {
+            nix_output(
+                &engines,
+                &NixOptions {
+                    indent: IndentStyle::Spaces(2),
+                    ..NixOptions::default()
+                },
+                None,
+                false,
+                false,
+                shape,
+            )
+            .unwrap()
+        };
+
+        let attrset = shaped(OutputShape::Attrset);
+        assert!(attrset.starts_with("{\n  \"A\" = {\n    urls = ["));
+        assert!(attrset.contains("\n  };\n  \"B\" = {\n    urls = ["));
+        assert!(attrset.ends_with("  };\n}"));
+        assert!(rnix::Root::parse(&attrset).ok().is_ok());
+
+        let list = shaped(OutputShape::List);
+        assert!(list.starts_with("[\n  {\n    name = \"A\";\n    urls = ["));
+        assert!(list.contains("\n  }\n  {\n    name = \"B\";\n    urls = ["));
+        assert!(list.ends_with("  }\n]"));
+        assert!(rnix::Root::parse(&list).ok().is_ok());
+    }
+
+    #[test]
+    fn module_output() {
+        let engines = [engine("https://a.example.com/", "A")];
+        let nix = nix_output(
+            &engines,
+            &NixOptions {
+                indent: IndentStyle::Spaces(2),
+                ..NixOptions::default()
+            },
+            Some("programs.firefox.profiles.me.search.engines"),
+            false,
+            false,
+            OutputShape::Attrset,
+        )
+        .unwrap();
+
+        assert!(nix.starts_with(
+            "{ config, ... }:\n{\n  programs.firefox.profiles.me.search.engines = {\n    \"A\" = {\n      urls = ["
+        ));
+        assert!(nix.ends_with("    };\n  };\n}"));
+        assert!(rnix::Root::parse(&nix).ok().is_ok());
+
+        // A multi-line description stays one escaped line when the module indents it.
+        let mut multi_line = engine("https://a.example.com/", "A");
+        multi_line.opensearch.description = "line one\nline two".to_string();
+        let nix = nix_output(
+            &[multi_line],
+            &NixOptions::default(),
+            Some(DEFAULT_MODULE_PATH),
+            false,
+            false,
+            OutputShape::Attrset,
+        )
+        .unwrap();
+
+        assert!(nix.contains(r#"description = "line one\nline two";"#));
+        assert!(rnix::Root::parse(&nix).ok().is_ok());
+
+        let args = Args::try_parse_from(["test", "https://example.com", "--module"]).unwrap();
+        assert_eq!(args.module_path, DEFAULT_MODULE_PATH);
+        assert!(Args::try_parse_from([
+            "test",
+            "https://example.com",
+            "--module-path",
+            "search.engines",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn firefox_policy_shape() {
+        let mut aliased = engine("https://a.example.com/", "A");
+        aliased.aliases = vec!["@a".to_string(), "@alpha".to_string()];
+        let engines = [aliased, engine("https://b.example.com/", "B")];
+
+        let policy =
+            serde_json::from_str::<serde_json::Value>(&firefox_policy_output(&engines).unwrap())
+                .unwrap();
+        let added = &policy["policies"]["SearchEngines"]["Add"];
+
+        assert_eq!(added[0]["Name"], "A");
+        assert_eq!(added[0]["Alias"], "@a");
+        assert_eq!(added[1]["URLTemplate"], "https://example.com/search");
+        assert!(added[1].get("Alias").is_none());
+        assert!(Args::try_parse_from([
+            "test",
+            "https://example.com",
+            "--format",
+            "firefox-policy"
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn annotated_output() {
+        let mut annotated = engine("https://a.example.com/", "A");
+        annotated.descriptor = "https://a.example.com/opensearch.xml".to_string();
+        annotated.opensearch.search_form =
+            Some(Url::parse("https://a.example.com/advanced").unwrap());
+        let engines = [annotated, engine("https://b.example.com/", "B")];
+
+        let nix = nix_output(
+            &engines,
+            &NixOptions::default(),
+            None,
+            true,
+            false,
+            OutputShape::Attrset,
+        )
+        .unwrap();
+
+        assert!(nix.starts_with(
+            "{\n    # Generated from https://a.example.com/opensearch.xml\n    # Search form: https://a.example.com/advanced\n    \"A\" = {\n"
+        ));
+        assert!(nix.contains("    # Generated from https://b.example.com/\n    \"B\" = {\n"));
+        assert!(rnix::Root::parse(&nix).ok().is_ok());
+    }
+
+    #[test]
+    fn homepage_comments() {
+        let mut with_form = engine("https://a.example.com/", "A");
+        with_form.descriptor = "https://a.example.com/opensearch/a.xml".to_string();
+        with_form.opensearch.search_form =
+            Some(Url::parse("https://a.example.com/advanced").unwrap());
+        let mut without_form = engine("https://b.example.com/", "B");
+        without_form.descriptor = "https://b.example.com/opensearch/b.xml".to_string();
+        let mut from_file = engine("b.xml", "C");
+        from_file.descriptor = "b.xml".to_string();
+
+        assert_eq!(
+            homepage(&with_form).map(String::from).as_deref(),
+            Some("https://a.example.com/advanced")
+        );
+        assert_eq!(
+            homepage(&without_form).map(String::from).as_deref(),
+            Some("https://b.example.com/")
+        );
+        assert_eq!(
+            homepage(&from_file),
+            from_file.opensearch.urls[0].template.join("/").ok()
+        );
+
+        let nix = nix_output(
+            &[with_form, without_form],
+            &NixOptions::default(),
+            None,
+            false,
+            true,
+            OutputShape::Attrset,
+        )
+        .unwrap();
+
+        assert!(nix.contains("    # homepage: https://a.example.com/advanced\n    \"A\" = {\n"));
+        assert!(nix.contains("    # homepage: https://b.example.com/\n    \"B\" = {\n"));
+        assert!(rnix::Root::parse(&nix).ok().is_ok());
+    }
+
+    #[test]
+    fn json_output_shape() {
+        let single = [engine("https://a.example.com/", "A")];
+        let multiple = [
+            engine("https://a.example.com/", "A"),
+            engine("https://b.example.com/", "B"),
+        ];
+
+        let single_json =
+            serde_json::from_str::<serde_json::Value>(&json_output(&single).unwrap()).unwrap();
+        let multiple_json =
+            serde_json::from_str::<serde_json::Value>(&json_output(&multiple).unwrap()).unwrap();
+
+        assert_eq!(single_json["short_name"], "A");
+        assert_eq!(multiple_json["A"]["short_name"], "A");
+        assert_eq!(multiple_json["B"]["urls"][0]["type"], "text/html");
+    }
+
+    #[test]
+    fn yaml_output_golden() {
+        let sample = Engine {
+            opensearch: parse_opensearch(SELFTEST_DESCRIPTOR).unwrap(),
+            ..engine("sample", "Example")
+        };
+
+        assert_eq!(
+            yaml_output(&[sample]).unwrap(),
+            r#"short_name: Example
+description: Search Example
+icon: https://example.com/favicon.ico
+urls:
+- template: https://example.com/search
+  type: text/html
+  method: GET
+  rel: results
+  index_offset: 1
+  page_offset: 1
+  params:
+  - name: q
+    value: '{searchTerms}'
+  - name: source
+    value: opensearch
+- template: https://example.com/suggest
+  type: application/x-suggestions+json
+  method: GET
+  rel: results
+  index_offset: 1
+  page_offset: 1
+  params:
+  - name: q
+    value: '{searchTerms}'
+languages: []
+input_encodings:
+- UTF-8
+output_encodings: []
+"#
+        );
+
+        let multiple = yaml_output(&[engine("a", "A"), engine("b", "B")]).unwrap();
+        assert!(multiple.starts_with("A:\n  short_name: A\n"));
+        assert!(multiple.contains("\nB:\n  short_name: B\n"));
+    }
+
+    #[test]
+    fn select_opensearch_url_index() {
+        let website = Url::parse("https://example.com/").unwrap();
+        let urls = [
+            Url::parse("https://example.com/de.xml").unwrap(),
+            Url::parse("https://example.com/en.xml").unwrap(),
+        ];
+
+        assert_eq!(
+            choose_opensearch_url(&urls, &website, None, false).unwrap(),
+            urls[0]
+        );
+        assert_eq!(
+            choose_opensearch_url(&urls, &website, Some(1), false).unwrap(),
+            urls[1]
+        );
+        assert!(matches!(
+            choose_opensearch_url(&urls, &website, Some(2), false),
+            Err(Error::SelectOutOfRange { count: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn suggestions_note() {
+        let html_only = engine("https://a.example.com/", "A");
+
+        assert_eq!(
+            missing_suggestions_note(&html_only.source, &html_only.opensearch).as_deref(),
+            Some("note: https://a.example.com/ has no suggestions url (found: text/html)")
+        );
+
+        let mut with_suggestions = engine("https://b.example.com/", "B");
+        let mut suggestions = with_suggestions.opensearch.urls[0].clone();
+        suggestions.rel = OpenSearchRel::Suggestions;
+        with_suggestions.opensearch.urls.push(suggestions);
+
+        assert_eq!(
+            missing_suggestions_note(&with_suggestions.source, &with_suggestions.opensearch),
+            None
+        );
+    }
+
+    #[test]
+    fn check_summaries() {
+        let mut summary = String::new();
+        let passing = engine("https://a.example.com/", "A");
+
+        assert!(check_summary(
+            &passing.source,
+            &passing.opensearch,
+            &[],
+            &mut summary
+        ));
+        assert_eq!(
+            summary,
+            r#"https://a.example.com/: "A"
+    url: https://example.com/search (type: text/html, rel: results)
+    warning: missing recommended Description
+    warning: missing recommended Image
+    warning: missing recommended suggestions Url
+    ok
+"#
+        );
+
+        let mut summary = String::new();
+        let mut failing = engine("https://b.example.com/", "");
+        failing.opensearch.urls.clear();
+
+        assert!(!check_summary(
+            &failing.source,
+            &failing.opensearch,
+            &[],
+            &mut summary
+        ));
+        assert!(summary.contains("    error: missing ShortName\n"));
+        assert!(!summary.contains("no ShortName to name the engine by"));
+        assert!(
+            summary.contains("    error: \"\" defines no search results url (found: no urls)\n")
+        );
+        assert!(summary.ends_with("    failed\n"));
+        assert!(Args::try_parse_from([
+            "test",
+            "https://example.com",
+            "--check",
+            "--output",
+            "out.nix",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn diffs() {
+        let existing = "{\n  \"A\" = {\n    description = \"Old\";\n  };\n}\n";
+        let generated = "{\n    \"A\" = {\n        description = \"New\";\n    };\n}\n";
+
+        assert_eq!(unified_diff(existing, existing, "a.nix", false), None);
+        assert_eq!(
+            unified_diff(existing, generated, "a.nix", true).unwrap(),
+            "--- a.nix\n+++ a.nix (generated)\n@@ -1,5 +1,5 @@\n {\n \"A\" = {\n-description = \"Old\";\n+description = \"New\";\n };\n }\n"
+        );
+
+        let reindented = generated.replace("description = \"New\"", "description = \"Old\"");
+        assert!(unified_diff(existing, &reindented, "a.nix", false).is_some());
+        assert_eq!(unified_diff(existing, &reindented, "a.nix", true), None);
+        assert!(
+            Args::try_parse_from(["test", "https://example.com", "--ignore-whitespace"]).is_err()
+        );
+    }
+
+    #[test]
+    fn final_newline() {
+        assert_eq!(finish_output("a = 1;", true), "a = 1;\n");
+        assert_eq!(finish_output("a = 1;\n\n", true), "a = 1;\n");
+        assert_eq!(finish_output("a = 1;\n", false), "a = 1;");
+    }
+
+    #[tokio::test]
+    async fn output_file_final_newline() {
+        let input =
+            std::env::temp_dir().join(format!("opensearch-newline-{}.xml", std::process::id()));
+        let output = input.with_extension("nix");
+        std::fs::write(&input, SELFTEST_DESCRIPTOR).unwrap();
+
+        for (flag, ending) in [(None, "};\n"), (Some("--no-final-newline"), "};")] {
+            let mut args = vec!["test", "-i", input.to_str().unwrap()];
+            args.extend(["-o", output.to_str().unwrap(), "--force"]);
+            args.extend(flag);
+            run(&Args::try_parse_from(args).unwrap()).await.unwrap();
+
+            let written = std::fs::read_to_string(&output).unwrap();
+            assert_eq!(&written[written.len() - ending.len()..], ending);
+            assert!(!written.ends_with("\n\n"));
+        }
+
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn output_refuses_to_overwrite() {
+        let path =
+            std::env::temp_dir().join(format!("opensearch-output-{}.nix", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        write_output("first\n", &path, false).unwrap();
+        assert!(matches!(
+            write_output("second\n", &path, false),
+            Err(Error::OutputExists(_))
+        ));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\n");
+
+        write_output("second\n", &path, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn print_descriptor_url_skips_fetch() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<html><head>
+                    <link rel="search" type="application/opensearchdescription+xml" href="/a.xml">
+                    <link rel="search" type="application/opensearchdescription+xml" href="/b.xml">
+                </head></html>"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/a.xml"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let args = Args::try_parse_from(["test", &server.uri(), "--print-descriptor-url"]).unwrap();
+        run(&args).await.unwrap();
+
+        assert!(Args::try_parse_from(["test", "--print-descriptor-url"]).is_err());
+        assert!(Args::try_parse_from([
+            "test",
+            "--descriptor-url",
+            "https://example.com/opensearch.xml",
+            "--print-descriptor-url",
+        ])
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn lenient_discovery_verifies() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<html><head>
+                    <link rel="search" href="/not-a-descriptor">
+                    <link rel="search" href="/opensearch.xml">
+                </head></html>"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(path("/not-a-descriptor"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html></html>"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/opensearch.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<OpenSearchDescription>
+                    <ShortName>Mock</ShortName>
+                    <Url type="text/html" template="/results?q={searchTerms}" />
+                </OpenSearchDescription>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let website = Url::parse(&server.uri()).unwrap();
+
+        let args = Args::try_parse_from(["test", &server.uri()]).unwrap();
+        assert!(fetch_opensearch_raw(&fetcher, &website, &args)
+            .await
+            .is_err());
+
+        let args = Args::try_parse_from(["test", &server.uri(), "--lenient-discovery"]).unwrap();
+        let (opensearch_url, _) = fetch_opensearch_raw(&fetcher, &website, &args)
+            .await
+            .unwrap();
+        assert_eq!(opensearch_url, website.join("/opensearch.xml").unwrap());
+    }
+
+    #[tokio::test]
+    async fn website_to_nix_offline() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<html><head>
+                    <link rel="search" type="application/opensearchdescription+xml" href="/search/opensearch.xml">
+                </head></html>"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/search/opensearch.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<?xml version="1.0"?>
+                <OpenSearchDescription>
+                    <ShortName>Mock</ShortName>
+                    <Description>Mock search</Description>
+                    <Image height="16" width="16" type="image/png">icon.png</Image>
+                    <Url type="text/html" template="/results?q={searchTerms}" />
+                </OpenSearchDescription>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let path = std::env::temp_dir().join(format!("opensearch-mock-{}.nix", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let args =
+            Args::try_parse_from(["test", &server.uri(), "--output", path.to_str().unwrap()])
+                .unwrap();
+        run(&args).await.unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!(
+                r#""Mock" = {{
+    urls = [
+        {{
+            template = "{uri}/results";
+            type = "text/html";
+            params = [
+                {{
+                    name = "q";
+                    value = "{{searchTerms}}";
+                }}
+            ];
+        }}
+    ];
+    iconUpdateURL = "{uri}/search/icon.png";
+    definedAliases = [ "@mock" ];
+    description = "Mock search";
+}};
+"#,
+                uri = server.uri()
+            )
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn header_syntax() {
+        assert_eq!(
+            parse_header("Authorization: Bearer token").unwrap(),
+            (
+                HeaderName::from_static("authorization"),
+                HeaderValue::from_static("Bearer token")
+            )
+        );
+        assert_eq!(
+            parse_header("Cookie:a=b; c=d").unwrap().1,
+            HeaderValue::from_static("a=b; c=d")
+        );
+        assert!(parse_header("Authorization").is_err());
+        assert!(parse_header(": value").is_err());
+        assert!(parse_header("Bad Name: value").is_err());
+        assert!(parse_header("Name: line\nbreak").is_err());
+        assert!(Args::try_parse_from(["test", "https://example.com", "--header", "nope"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn custom_headers_sent() {
+        use wiremock::{
+            matchers::{header, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(path("/"))
+            .and(header("authorization", "Bearer token"))
+            .and(header("cookie", "session=1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<html><head>
+                    <link rel="search" type="application/opensearchdescription+xml" href="/opensearch.xml">
+                </head></html>"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/opensearch.xml"))
+            .and(header("authorization", "Bearer token"))
+            .and(header("cookie", "session=1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<OpenSearchDescription>
+                    <ShortName>Mock</ShortName>
+                    <Url type="text/html" template="/results?q={searchTerms}" />
+                </OpenSearchDescription>"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let args = Args::try_parse_from([
+            "test",
+            &server.uri(),
+            "--header",
+            "Authorization: Bearer token",
+            "--header",
+            "Cookie: session=1",
+            "--check",
+            "--quiet",
+            "--retries",
+            "0",
+        ])
+        .unwrap();
+        run(&args).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn input_file_base_url() {
+        let input =
+            std::env::temp_dir().join(format!("opensearch-base-{}.xml", std::process::id()));
+        let output = input.with_extension("nix");
+        let _ = std::fs::remove_file(&output);
+        std::fs::write(
+            &input,
+            r#"<?xml version="1.0"?>
             <OpenSearchDescription>
                 <ShortName>Test</ShortName>
-                <Image height="16" width ="16" type="image/x-icon">https://example.com/image.ico</Image>
-                <Image height="32" width ="32" type="image/x-icon">https://example.com/image.ico</Image>
+                <Image height="16" width="16" type="image/x-icon">/favicon.ico</Image>
                 <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
-                <Description>Hi there</Description>
-                <Url type="application/x-suggestions+json" template="https://example.com/json?q={searchTerms}" />
-                <Url type="application/x-suggestions+xml" template="https://example.com/xml?q={searchTerms}" />
-            </OpenSearchDescription>
-        "#;
-
-        let parsed = serde_xml_rs::from_str::<OpenSearchDescription>(raw).unwrap();
-        panic!("{:#?}", parsed);
+            </OpenSearchDescription>"#,
+        )
+        .unwrap();
+
+        let without_base = Args::try_parse_from(["test", "-i", input.to_str().unwrap()]).unwrap();
+        let error = run(&without_base).await.unwrap_err();
+        assert!(matches!(error, Error::MissingBaseUrl { .. }));
+        assert!(error.to_string().contains("pass --base-url"));
+
+        let with_base = Args::try_parse_from([
+            "test",
+            "-i",
+            input.to_str().unwrap(),
+            "--base-url",
+            "https://example.com/path/",
+            "-o",
+            output.to_str().unwrap(),
+        ])
+        .unwrap();
+        run(&with_base).await.unwrap();
+        assert!(std::fs::read_to_string(&output)
+            .unwrap()
+            .contains("iconUpdateURL = \"https://example.com/favicon.ico\";"));
+
+        assert!(Args::try_parse_from([
+            "test",
+            "https://example.com",
+            "--base-url",
+            "https://example.com",
+        ])
+        .is_err());
+        assert!(Args::try_parse_from([
+            "test",
+            "--urls-file",
+            "urls.txt",
+            "--base-url",
+            "https://example.com",
+        ])
+        .is_err());
+
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn urls_file_batch() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/good"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<link rel="search" type="application/opensearchdescription+xml" href="/opensearch.xml">"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(path("/no-link"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html></html>"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/opensearch.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<OpenSearchDescription>
+                    <ShortName>Good</ShortName>
+                    <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                </OpenSearchDescription>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let urls = std::env::temp_dir().join(format!("opensearch-urls-{}.txt", std::process::id()));
+        let output = urls.with_extension("nix");
+        let _ = std::fs::remove_file(&output);
+        std::fs::write(
+            &urls,
+            format!(
+                "# engines to migrate\n{uri}/good\n\n   \nnot a url\n{uri}/no-link\n",
+                uri = server.uri()
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            read_urls_file(&urls).unwrap(),
+            [
+                format!("{}/good", server.uri()),
+                "not a url".to_string(),
+                format!("{}/no-link", server.uri()),
+            ]
+        );
+
+        let args = Args::try_parse_from([
+            "test",
+            "--urls-file",
+            urls.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ])
+        .unwrap();
+
+        assert!(matches!(
+            run(&args).await,
+            Err(Error::BatchFailed { failed: 2 })
+        ));
+        assert!(std::fs::read_to_string(&output)
+            .unwrap()
+            .starts_with("\"Good\" = {"));
+
+        std::fs::remove_file(&urls).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn live_validation() {
+        use wiremock::{
+            matchers::{path, query_param},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(path("/search"))
+            .and(query_param("q", "rust lang"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(path("/suggest"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(path("/opensearch.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                r#"<OpenSearchDescription>
+                    <ShortName>Mock</ShortName>
+                    <Url type="text/html" template="{uri}/search?q={{searchTerms}}" />
+                    <Url type="application/x-suggestions+json" template="{uri}/suggest?q={{searchTerms}}" />
+                    <Url type="text/html" method="post" template="{uri}/post">
+                        <Param name="q" value="{{searchTerms}}" />
+                    </Url>
+                    <Query role="example" searchTerms="rust lang" />
+                </OpenSearchDescription>"#,
+                uri = server.uri()
+            )))
+            .mount(&server)
+            .await;
+
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let descriptor = format!("{}/opensearch.xml", server.uri());
+        let raw = get_opensearch_raw(&fetcher, Url::parse(&descriptor).unwrap())
+            .await
+            .unwrap();
+        let opensearch = parse_opensearch(&raw).unwrap();
+        let mut report = String::new();
+
+        assert_eq!(
+            validate_live(&fetcher, "mock", &opensearch, &mut report).await,
+            (2, 1)
+        );
+        assert_eq!(
+            report,
+            format!(
+                "mock: live query for \"rust lang\"\n    \
+                ok: 200 OK {uri}/search?q=rust+lang\n    \
+                failed: 404 Not Found {uri}/suggest?q=rust+lang\n    \
+                skipped: POST {uri}/post\n",
+                uri = server.uri()
+            )
+        );
+
+        let args = Args::try_parse_from([
+            "test",
+            "--descriptor-url",
+            &descriptor,
+            "--validate-live",
+            "--quiet",
+        ])
+        .unwrap();
+        assert!(matches!(
+            run(&args).await,
+            Err(Error::LiveQueryFailed {
+                failed: 1,
+                total: 2
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn redirected_page_relative_href() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(301).insert_header("location", "/docs/index.html"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/docs/index.html"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<html><head>
+                    <link rel="search" type="application/opensearchdescription+xml" href="opensearch.xml">
+                </head></html>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let website = Url::parse(&server.uri()).unwrap();
+        let fetcher = Fetcher::new(DEFAULT_TIMEOUT, 0).unwrap();
+        let args = Args::try_parse_from(["test", website.as_str(), "--quiet"]).unwrap();
+
+        assert_eq!(
+            discover_opensearch_urls(&fetcher, &website, &args)
+                .await
+                .unwrap(),
+            [website.join("/docs/opensearch.xml").unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn script_rendered_discovery() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<!doctype html><html><head>
+                    <script defer src="/static/js/main.1c2d.js"></script>
+                </head><body><div id="app"></div></body></html>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let args = Args::try_parse_from(["test", &server.uri(), "--retries", "0"]).unwrap();
+        let error = run(&args).await.unwrap_err();
+
+        assert!(matches!(error, Error::ScriptRendered(_)));
+        assert_eq!(exit_code(&error), DISCOVERY_EXIT_CODE);
+        assert!(error
+            .to_string()
+            .ends_with("descriptor may be injected via JavaScript; try --descriptor-url"));
+    }
+
+    #[tokio::test]
+    async fn exit_codes() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+
+        for (page, descriptor) in [
+            ("/no-link", None),
+            ("/invalid", Some("<OpenSearchDescription><ShortName>")),
+            (
+                "/no-results",
+                Some(
+                    r#"<OpenSearchDescription>
+                        <ShortName>Mock</ShortName>
+                        <Url type="application/x-suggestions+json" rel="suggestions" template="/json?q={searchTerms}" />
+                    </OpenSearchDescription>"#,
+                ),
+            ),
+        ] {
+            let link = descriptor.map_or(String::new(), |_| {
+                format!(
+                    r#"<link rel="search" type="application/opensearchdescription+xml" href="{}.xml">"#,
+                    page
+                )
+            });
+
+            Mock::given(path(page))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string(format!("<html><head>{}</head></html>", link)),
+                )
+                .mount(&server)
+                .await;
+
+            if let Some(descriptor) = descriptor {
+                Mock::given(path(format!("{}.xml", page)))
+                    .respond_with(ResponseTemplate::new(200).set_body_string(descriptor))
+                    .mount(&server)
+                    .await;
+            }
+        }
+
+        let run_exit_code = |website: String| async move {
+            let args =
+                Args::try_parse_from(["test", &website, "--retries", "0", "--quiet"]).unwrap();
+            exit_code(&run(&args).await.unwrap_err())
+        };
+
+        // Nothing listens on the discard port, so the connection is refused.
+        assert_eq!(
+            run_exit_code("http://127.0.0.1:9/".to_string()).await,
+            NETWORK_EXIT_CODE
+        );
+        assert_eq!(
+            run_exit_code(format!("{}/no-link", server.uri())).await,
+            DISCOVERY_EXIT_CODE
+        );
+        assert_eq!(
+            run_exit_code(format!("{}/invalid", server.uri())).await,
+            PARSE_EXIT_CODE
+        );
+        assert_eq!(
+            run_exit_code(format!("{}/no-results", server.uri())).await,
+            VALIDATION_EXIT_CODE
+        );
+        assert_eq!(exit_code(&Error::Aborted), ABORTED_EXIT_CODE);
     }
 }
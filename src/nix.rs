@@ -0,0 +1,1143 @@
+//! Generation of NixOS search engine definitions.
+
+use std::fmt::{self, Write};
+
+use mime::Mime;
+
+use crate::{
+    Error, OpenSearchDescription, OpenSearchImage, OpenSearchMethod, OpenSearchRel, OpenSearchUrl,
+};
+
+/// Escapes a value so it can be embedded inside a double-quoted Nix string.
+///
+/// Line breaks and tabs are escaped too, so the string stays on one line and re-indenting the
+/// output can't change its value.
+pub fn escape_nix_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+
+    while let Some(character) = chars.next() {
+        match character {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            '\r' => escaped += "\\r",
+            '\t' => escaped += "\\t",
+            '$' if chars.peek() == Some(&'{') => escaped += "\\$",
+            _ => escaped.push(character),
+        }
+    }
+
+    escaped
+}
+
+/// Converts a human-readable name into a lowercase, dash-separated Nix attribute name.
+pub fn slugify(value: &str) -> String {
+    let mut slug = String::with_capacity(value.len());
+
+    for character in value.chars() {
+        if character.is_alphanumeric() {
+            slug.extend(character.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    if slug.ends_with('-') {
+        slug.pop();
+    }
+
+    slug
+}
+
+/// Collapses generated Nix onto a single line, dropping its comments, which would otherwise run
+/// on to the end of it.
+pub fn compact_nix(nix: &str) -> String {
+    let mut compact = String::with_capacity(nix.len());
+    let mut chars = nix.chars();
+    let mut pending_space = false;
+
+    while let Some(character) = chars.next() {
+        match character {
+            '#' => {
+                chars.by_ref().find(|&character| character == '\n');
+                pending_space = true;
+            }
+            character if character.is_whitespace() => pending_space = true,
+            character => {
+                if pending_space && !compact.is_empty() {
+                    compact.push(' ');
+                }
+
+                pending_space = false;
+                compact.push(character);
+
+                if character == '"' {
+                    // Copy the string through unchanged, up to its unescaped closing quote.
+                    while let Some(character) = chars.next() {
+                        compact.push(character);
+
+                        match character {
+                            '\\' => compact.extend(chars.next()),
+                            '"' => break,
+                            _ => (),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    compact
+}
+
+/// The whitespace used for each level of nesting in generated Nix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentStyle {
+    Spaces(usize),
+    Tabs,
+}
+
+impl Default for IndentStyle {
+    fn default() -> Self {
+        Self::Spaces(4)
+    }
+}
+
+impl IndentStyle {
+    /// The leading whitespace for `depth` levels of nesting.
+    pub fn level(&self, depth: usize) -> String {
+        match self {
+            Self::Spaces(width) => " ".repeat(width * depth),
+            Self::Tabs => "\t".repeat(depth),
+        }
+    }
+}
+
+/// How a url's query string is emitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueryStyle {
+    /// Split out of the template into a decoded `params` list.
+    #[default]
+    Params,
+    /// Split out of the template into a `params` list, keeping values percent-encoded.
+    RawParams,
+    /// Left in the template, without a `params` list.
+    Template,
+}
+
+/// How a url's params are emitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParamsShape {
+    /// A list of `{ name = ...; value = ...; }` attribute sets, keeping repeated names.
+    #[default]
+    List,
+    /// An attribute set keyed by name, where a repeated name keeps its last value.
+    Attrset,
+}
+
+/// Words Nix reserves, which can't be used as bare attribute names.
+const NIX_KEYWORDS: &[&str] = &[
+    "if", "then", "else", "assert", "with", "let", "in", "rec", "inherit", "or",
+];
+
+/// Quotes an attribute name unless it's a valid Nix identifier that isn't a keyword.
+fn nix_attr_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|character| {
+            character.is_ascii_alphanumeric() || matches!(character, '_' | '\'' | '-')
+        });
+
+    if is_identifier && !NIX_KEYWORDS.contains(&name) {
+        name.to_string()
+    } else {
+        format!("\"{}\"", escape_nix_string(name))
+    }
+}
+
+/// Everything that shapes the Nix generated for an engine. The defaults emit an attribute keyed
+/// by the engine's short name, as [`OpenSearchDescription::to_nix`] does.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NixOptions {
+    /// The attribute name to emit the engine under, in place of its short name.
+    pub attr_name: Option<String>,
+    /// Emits the engine as a list element carrying its name as a field, instead of an attribute.
+    pub list_item: bool,
+    /// The aliases that trigger the engine.
+    pub aliases: Vec<String>,
+    /// A data uri emitted as the engine's `icon` in place of linking to the preferred image.
+    pub inline_icon: Option<String>,
+    /// Lists the images other than the preferred one in a comment after it.
+    pub all_icons: bool,
+    /// How often, in days, a linked icon is refreshed.
+    pub icon_update_interval: Option<u32>,
+    /// Emits only the urls of these types, compared without parameters; all urls but feeds
+    /// when empty.
+    pub url_types: Vec<Mime>,
+    /// Emits Atom and RSS feed urls, which `search.engines` has no use for, without them being
+    /// listed in `url_types`.
+    pub include_feeds: bool,
+    pub query_style: QueryStyle,
+    pub params_shape: ParamsShape,
+    /// Emits each url's params sorted by name instead of in the order the template lists them.
+    pub sort_params: bool,
+    pub indent: IndentStyle,
+    /// Emits the engine on a single line, without comments.
+    pub compact: bool,
+}
+
+impl NixOptions {
+    /// Whether `url` passes the `url_types` filter, or isn't a feed when there's no filter.
+    fn emits_url(&self, url: &OpenSearchUrl) -> bool {
+        if self.url_types.is_empty() {
+            return self.include_feeds || !url.is_feed();
+        }
+
+        self.include_feeds && url.is_feed()
+            || self
+                .url_types
+                .iter()
+                .any(|url_type| url.template_type.essence_str() == url_type.essence_str())
+    }
+}
+
+impl OpenSearchDescription {
+    /// Converts the engine into a Nix attribute, keyed by its short name.
+    pub fn to_nix(&self) -> Result<String, Error> {
+        self.to_nix_with(&NixOptions::default())
+    }
+
+    /// Converts the engine into Nix shaped by `options`.
+    pub fn to_nix_with(&self, options: &NixOptions) -> Result<String, Error> {
+        self.validate_schemes(&[])?;
+        self.to_nix_string(options)
+    }
+
+    /// Converts the engine into Nix shaped by `options`, leaving its url schemes unchecked as
+    /// [`into_nix`](Self::into_nix) does.
+    pub fn to_nix_string(&self, options: &NixOptions) -> Result<String, Error> {
+        let mut nix = String::new();
+        self.into_nix(options, &mut nix)?;
+        Ok(nix)
+    }
+
+    /// Writes the engine to `out` as Nix shaped by `options`.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn into_nix(&self, options: &NixOptions, out: &mut impl Write) -> Result<(), Error> {
+        if options.compact {
+            let mut pretty = String::new();
+            self.into_nix(
+                &NixOptions {
+                    compact: false,
+                    ..options.clone()
+                },
+                &mut pretty,
+            )?;
+
+            out.write_str(&compact_nix(&pretty))?;
+            return Ok(());
+        }
+
+        self.validate()?;
+
+        let field = options.indent.level(1);
+        let attr_name = options.attr_name.as_deref().unwrap_or(&self.short_name);
+
+        if options.list_item {
+            out.write_str("{\n")?;
+        } else {
+            writeln!(out, "\"{}\" = {{", escape_nix_string(attr_name))?;
+        }
+
+        if options.list_item || attr_name != self.short_name {
+            writeln!(
+                out,
+                "{}name = \"{}\";",
+                field,
+                escape_nix_string(&self.short_name)
+            )?;
+        }
+
+        writeln!(out, "{}urls = [", field)?;
+
+        for url in self
+            .urls
+            .iter()
+            .filter(|url| url.rel != OpenSearchRel::SelfLink && options.emits_url(url))
+        {
+            url.into_nix(options, out)?;
+        }
+
+        writeln!(out, "{}];", field)?;
+
+        let icon = Self::select_icon(&self.images);
+
+        if let Some(data_uri) = &options.inline_icon {
+            writeln!(out, "{}icon = \"{}\";", field, escape_nix_string(data_uri))?;
+        } else if let Some(image) = icon {
+            image.into_nix(options, out)?;
+        }
+
+        if options.all_icons && self.images.len() > 1 {
+            // A stable sort puts the selected icon first, ahead of any equally ranked images.
+            let mut sorted_images = self.images.iter().collect::<Vec<_>>();
+            sorted_images.sort();
+
+            writeln!(out, "{}# Alternative icons:", field)?;
+
+            for image in sorted_images.into_iter().skip(1) {
+                writeln!(
+                    out,
+                    "{}#   {} ({}, {}x{})",
+                    field,
+                    image.url,
+                    image
+                        .image_type
+                        .as_ref()
+                        .map_or("unknown type", Mime::essence_str),
+                    image.width.unwrap_or_default(),
+                    image.height.unwrap_or_default()
+                )?;
+            }
+        }
+
+        if !options.aliases.is_empty() {
+            write!(out, "{}definedAliases = [", field)?;
+
+            for alias in &options.aliases {
+                write!(out, " \"{}\"", escape_nix_string(alias))?;
+            }
+
+            out.write_str(" ];\n")?;
+        }
+
+        write!(
+            out,
+            "{}description = \"{}\";\n}}",
+            field,
+            escape_nix_string(&self.description)
+        )?;
+
+        if !options.list_item {
+            out.write_str(";")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl OpenSearchUrl {
+    #[allow(clippy::wrong_self_convention)]
+    pub(crate) fn into_nix(&self, options: &NixOptions, out: &mut impl Write) -> fmt::Result {
+        let indent = options.indent;
+        let url = indent.level(2);
+        let field = indent.level(3);
+        let param = indent.level(4);
+        let param_field = indent.level(5);
+
+        writeln!(out, "{}{{", url)?;
+        let (template, mut params) = match options.query_style {
+            QueryStyle::Params => (self.queryless_template(), self.all_params()),
+            QueryStyle::RawParams => (self.queryless_template(), self.raw_params()),
+            QueryStyle::Template => (self.full_template(), self.params.clone()),
+        };
+
+        if options.sort_params {
+            // A stable sort keeps repeated names in the order the engine expects them.
+            params.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+
+        writeln!(
+            out,
+            "{}template = \"{}\";",
+            field,
+            escape_nix_string(&template)
+        )?;
+        writeln!(
+            out,
+            "{}type = \"{}\";",
+            field,
+            escape_nix_string(self.template_type.as_ref())
+        )?;
+
+        if self.method != OpenSearchMethod::Get {
+            writeln!(out, "{}method = \"{}\";", field, self.method)?;
+        }
+
+        if self.rel != OpenSearchRel::Results {
+            writeln!(
+                out,
+                "{}rel = \"{}\";",
+                field,
+                escape_nix_string(&self.rel.to_string())
+            )?;
+        }
+
+        if !params.is_empty() && options.params_shape == ParamsShape::Attrset {
+            writeln!(out, "{}params = {{", field)?;
+
+            // Later values of a repeated name win, as they would in a Nix attribute set.
+            let mut values = Vec::<(&str, &str)>::new();
+
+            for parameter in &params {
+                match values.iter_mut().find(|(name, _)| *name == parameter.name) {
+                    Some((_, value)) => *value = &parameter.value,
+                    None => values.push((&parameter.name, &parameter.value)),
+                }
+            }
+
+            for (name, value) in values {
+                writeln!(
+                    out,
+                    "{}{} = \"{}\";",
+                    param,
+                    nix_attr_name(name),
+                    escape_nix_string(value)
+                )?;
+            }
+
+            writeln!(out, "{}}};", field)?;
+        } else if !params.is_empty() {
+            writeln!(out, "{}params = [", field)?;
+
+            for parameter in params {
+                writeln!(out, "{}{{", param)?;
+                writeln!(
+                    out,
+                    "{}name = \"{}\";",
+                    param_field,
+                    escape_nix_string(&parameter.name)
+                )?;
+                writeln!(
+                    out,
+                    "{}value = \"{}\";",
+                    param_field,
+                    escape_nix_string(&parameter.value)
+                )?;
+                writeln!(out, "{}}}", param)?;
+            }
+
+            writeln!(out, "{}];", field)?;
+        }
+
+        writeln!(out, "{}}}", url)
+    }
+}
+
+impl OpenSearchImage {
+    #[allow(clippy::wrong_self_convention)]
+    pub(crate) fn into_nix(&self, options: &NixOptions, out: &mut impl Write) -> fmt::Result {
+        let indent = options.indent;
+
+        // Embedded images have nothing to update from.
+        let field = if self.is_data_uri() {
+            "icon"
+        } else {
+            "iconUpdateURL"
+        };
+
+        writeln!(
+            out,
+            "{}{} = \"{}\";",
+            indent.level(1),
+            field,
+            escape_nix_string(self.url.as_str())
+        )?;
+
+        if let Some(days) = options.icon_update_interval.filter(|_| !self.is_data_uri()) {
+            // Firefox counts the interval in milliseconds.
+            writeln!(
+                out,
+                "{}updateInterval = {} * 24 * 60 * 60 * 1000; # every {} day{}",
+                indent.level(1),
+                days,
+                days,
+                if days == 1 { "" } else { "s" }
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse_opensearch;
+
+    #[test]
+    fn escape_nix_strings() {
+        assert_eq!(escape_nix_string(r#"a "b" \c"#), r#"a \"b\" \\c"#);
+        assert_eq!(escape_nix_string("${x} $y"), r"\${x} $y");
+        assert_eq!(escape_nix_string("a\nb\r\tc"), r"a\nb\r\tc");
+    }
+
+    #[test]
+    fn escaped_description_parses() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Description>He said "hi" ${x}</Description>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let mut nix = String::new();
+        parsed.into_nix(&NixOptions::default(), &mut nix).unwrap();
+
+        assert!(nix.contains(r#"description = "He said \"hi\" \${x}";"#));
+        assert!(rnix::Root::parse(&format!("{{ {} }}", nix)).ok().is_ok());
+    }
+
+    #[test]
+    fn write_to_any_writer() {
+        /// Streams formatted text into a byte writer, as a caller would into a file.
+        struct IoWriter<W>(W);
+
+        impl<W: std::io::Write> Write for IoWriter<W> {
+            fn write_str(&mut self, text: &str) -> fmt::Result {
+                self.0.write_all(text.as_bytes()).map_err(|_| fmt::Error)
+            }
+        }
+
+        let parsed = parse_opensearch(
+            r#"<OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Image height="16" width="16" type="image/png">https://example.com/icon.png</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>"#,
+        )
+        .unwrap();
+        let options = NixOptions::default();
+
+        let mut string = String::from("# engines\n");
+        parsed.into_nix(&options, &mut string).unwrap();
+        assert_eq!(
+            string,
+            format!("# engines\n{}", parsed.to_nix_string(&options).unwrap())
+        );
+
+        let mut bytes = IoWriter(Vec::<u8>::new());
+        parsed.into_nix(&options, &mut bytes).unwrap();
+        assert_eq!(
+            String::from_utf8(bytes.0).unwrap(),
+            parsed.to_nix().unwrap()
+        );
+
+        /// Refuses every write, like a full disk.
+        struct Full;
+
+        impl Write for Full {
+            fn write_str(&mut self, _: &str) -> fmt::Result {
+                Err(fmt::Error)
+            }
+        }
+
+        assert!(matches!(
+            parsed.into_nix(&options, &mut Full),
+            Err(Error::WriteNix(_))
+        ));
+    }
+
+    #[test]
+    fn slugify_names() {
+        assert_eq!(slugify("My Engine"), "my-engine");
+        assert_eq!(slugify("  DuckDuckGo (HTML)!  "), "duckduckgo-html");
+        assert_eq!(slugify("Wiki -- Search"), "wiki-search");
+    }
+
+    #[test]
+    fn custom_attr_name() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>My Engine</ShortName>
+                <Url type="text/html" template="https://example.com/search" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+
+        let mut nix = String::new();
+        parsed
+            .into_nix(
+                &NixOptions {
+                    attr_name: Some("my-engine".to_string()),
+                    ..NixOptions::default()
+                },
+                &mut nix,
+            )
+            .unwrap();
+        assert!(nix.starts_with("\"my-engine\" = {\n    name = \"My Engine\";\n"));
+
+        let mut nix = String::new();
+        parsed
+            .into_nix(
+                &NixOptions {
+                    attr_name: Some("My Engine".to_string()),
+                    ..NixOptions::default()
+                },
+                &mut nix,
+            )
+            .unwrap();
+        assert!(nix.starts_with("\"My Engine\" = {\n    urls = [\n"));
+    }
+
+    #[test]
+    fn placeholders_survive() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}&amp;ie={inputEncoding}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+
+        assert_eq!(
+            parsed.to_nix().unwrap(),
+            r#""Test" = {
+    urls = [
+        {
+            template = "https://example.com/search";
+            type = "text/html";
+            params = [
+                {
+                    name = "q";
+                    value = "{searchTerms}";
+                }
+                {
+                    name = "ie";
+                    value = "{inputEncoding}";
+                }
+            ];
+        }
+    ];
+    description = "";
+};"#
+        );
+    }
+
+    #[test]
+    fn defined_aliases() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>DuckDuckGo</ShortName>
+                <Description>Search DuckDuckGo</Description>
+                <Url type="text/html" template="https://duckduckgo.com/" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let mut nix = String::new();
+        parsed
+            .into_nix(
+                &NixOptions {
+                    aliases: vec!["@ddg".to_string(), "@duck".to_string()],
+                    ..NixOptions::default()
+                },
+                &mut nix,
+            )
+            .unwrap();
+
+        assert_eq!(
+            nix,
+            r#""DuckDuckGo" = {
+    urls = [
+        {
+            template = "https://duckduckgo.com/";
+            type = "text/html";
+        }
+    ];
+    definedAliases = [ "@ddg" "@duck" ];
+    description = "Search DuckDuckGo";
+};"#
+        );
+    }
+
+    #[test]
+    fn sorted_params() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}&amp;lang=en&amp;b=1" />
+            </OpenSearchDescription>
+        "#;
+
+        let url = &parse_opensearch(raw).unwrap().urls[0];
+        let param_names = |sort_params| {
+            let mut nix = String::new();
+            url.into_nix(
+                &NixOptions {
+                    sort_params,
+                    ..NixOptions::default()
+                },
+                &mut nix,
+            )
+            .unwrap();
+
+            nix.lines()
+                .filter_map(|line| line.trim().strip_prefix("name = "))
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(param_names(false), [r#""q";"#, r#""lang";"#, r#""b";"#]);
+        assert_eq!(param_names(true), [r#""b";"#, r#""lang";"#, r#""q";"#]);
+    }
+
+    const INDENT_SAMPLE: &str = r#"<?xml version="1.0"?>
+        <OpenSearchDescription>
+            <ShortName>Test</ShortName>
+            <Description>Hi there</Description>
+            <Image height="16" width="16" type="image/x-icon">https://example.com/icon.ico</Image>
+            <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+        </OpenSearchDescription>
+    "#;
+
+    #[test]
+    fn default_options() {
+        let parsed = parse_opensearch(INDENT_SAMPLE).unwrap();
+        let expected = r#""Test" = {
+    urls = [
+        {
+            template = "https://example.com/search";
+            type = "text/html";
+            params = [
+                {
+                    name = "q";
+                    value = "{searchTerms}";
+                }
+            ];
+        }
+    ];
+    iconUpdateURL = "https://example.com/icon.ico";
+    description = "Hi there";
+};"#;
+
+        let mut nix = String::new();
+        parsed.into_nix(&NixOptions::default(), &mut nix).unwrap();
+
+        assert_eq!(nix, expected);
+        assert_eq!(parsed.to_nix().unwrap(), expected);
+        assert_eq!(
+            parsed.to_nix_with(&NixOptions::default()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn compact_output() {
+        let parsed = parse_opensearch(INDENT_SAMPLE).unwrap();
+        let compact = parsed
+            .to_nix_with(&NixOptions {
+                compact: true,
+                icon_update_interval: Some(7),
+                ..NixOptions::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            compact,
+            r#""Test" = { urls = [ { template = "https://example.com/search"; type = "text/html"; params = [ { name = "q"; value = "{searchTerms}"; } ]; } ]; iconUpdateURL = "https://example.com/icon.ico"; updateInterval = 7 * 24 * 60 * 60 * 1000; description = "Hi there"; };"#
+        );
+        assert_eq!(
+            compact_nix(&parsed.to_nix().unwrap()),
+            compact.replace(" updateInterval = 7 * 24 * 60 * 60 * 1000;", "")
+        );
+        assert!(rnix::Root::parse(&format!("{{ {} }}", compact))
+            .ok()
+            .is_ok());
+
+        assert_eq!(
+            compact_nix("{\n  a = \"x  # \\\"y\";  # note\n  b = 1;\n}"),
+            "{ a = \"x  # \\\"y\"; b = 1; }"
+        );
+    }
+
+    #[test]
+    fn feeds_excluded() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <Url type="application/atom+xml" template="https://example.com/atom?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        assert!(parsed.urls[1].is_feed());
+
+        let excluded = parsed.to_nix().unwrap();
+        assert!(!excluded.contains("application/atom+xml"));
+        assert!(excluded.contains("text/html"));
+
+        let included = parsed
+            .to_nix_with(&NixOptions {
+                include_feeds: true,
+                ..NixOptions::default()
+            })
+            .unwrap();
+        assert!(included.contains("type = \"application/atom+xml\";"));
+        assert!(included.contains("template = \"https://example.com/atom\";"));
+    }
+
+    #[test]
+    fn list_item_and_url_types() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <Url type="application/x-suggestions+json" template="https://example.com/json?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let nix = parse_opensearch(raw)
+            .unwrap()
+            .to_nix_with(&NixOptions {
+                list_item: true,
+                url_types: vec!["application/x-suggestions+json".parse().unwrap()],
+                ..NixOptions::default()
+            })
+            .unwrap();
+
+        assert!(nix.starts_with("{\n    name = \"Test\";\n"));
+        assert!(nix.ends_with('}'));
+        assert_eq!(nix.matches("template = ").count(), 1);
+        assert!(nix.contains("https://example.com/json"));
+    }
+
+    #[test]
+    fn two_space_indent() {
+        let parsed = parse_opensearch(INDENT_SAMPLE).unwrap();
+
+        assert_eq!(
+            parsed
+                .to_nix_with(&NixOptions {
+                    indent: IndentStyle::Spaces(2),
+                    ..NixOptions::default()
+                })
+                .unwrap(),
+            r#""Test" = {
+  urls = [
+    {
+      template = "https://example.com/search";
+      type = "text/html";
+      params = [
+        {
+          name = "q";
+          value = "{searchTerms}";
+        }
+      ];
+    }
+  ];
+  iconUpdateURL = "https://example.com/icon.ico";
+  description = "Hi there";
+};"#
+        );
+    }
+
+    #[test]
+    fn tab_indent() {
+        let parsed = parse_opensearch(INDENT_SAMPLE).unwrap();
+
+        assert_eq!(
+            parsed
+                .to_nix_with(&NixOptions {
+                    indent: IndentStyle::Tabs,
+                    ..NixOptions::default()
+                })
+                .unwrap(),
+            "\"Test\" = {\n\
+            \turls = [\n\
+            \t\t{\n\
+            \t\t\ttemplate = \"https://example.com/search\";\n\
+            \t\t\ttype = \"text/html\";\n\
+            \t\t\tparams = [\n\
+            \t\t\t\t{\n\
+            \t\t\t\t\tname = \"q\";\n\
+            \t\t\t\t\tvalue = \"{searchTerms}\";\n\
+            \t\t\t\t}\n\
+            \t\t\t];\n\
+            \t\t}\n\
+            \t];\n\
+            \ticonUpdateURL = \"https://example.com/icon.ico\";\n\
+            \tdescription = \"Hi there\";\n\
+            };"
+        );
+    }
+
+    #[test]
+    fn inline_icon_replaces_update_url() {
+        let parsed = parse_opensearch(INDENT_SAMPLE).unwrap();
+        let mut nix = String::new();
+        parsed
+            .into_nix(
+                &NixOptions {
+                    inline_icon: Some("data:image/png;base64,aWNvbg==".to_string()),
+                    ..NixOptions::default()
+                },
+                &mut nix,
+            )
+            .unwrap();
+
+        assert!(nix.contains("    icon = \"data:image/png;base64,aWNvbg==\";\n"));
+        assert!(!nix.contains("iconUpdateURL"));
+    }
+
+    #[test]
+    fn icon_update_interval() {
+        let parsed = parse_opensearch(INDENT_SAMPLE).unwrap();
+        let nix_with_interval = |inline_icon: Option<&str>, interval| {
+            let mut nix = String::new();
+            parsed
+                .into_nix(
+                    &NixOptions {
+                        inline_icon: inline_icon.map(str::to_string),
+                        icon_update_interval: interval,
+                        ..NixOptions::default()
+                    },
+                    &mut nix,
+                )
+                .unwrap();
+            nix
+        };
+
+        let nix = nix_with_interval(None, Some(7));
+        assert!(nix.contains(
+            r#"    iconUpdateURL = "https://example.com/icon.ico";
+    updateInterval = 7 * 24 * 60 * 60 * 1000; # every 7 days
+"#
+        ));
+        assert!(rnix::Root::parse(&format!("{{ {} }}", nix)).ok().is_ok());
+
+        assert!(nix_with_interval(None, Some(1)).contains("; # every 1 day\n"));
+        assert!(!nix_with_interval(None, None).contains("updateInterval"));
+        assert!(
+            !nix_with_interval(Some("data:image/png;base64,aWNvbg=="), Some(7))
+                .contains("updateInterval")
+        );
+    }
+
+    #[test]
+    fn query_styles() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}&amp;t=ffab" />
+            </OpenSearchDescription>
+        "#;
+
+        let url = &parse_opensearch(raw).unwrap().urls[0];
+        let mut split = String::new();
+        let mut whole = String::new();
+        url.into_nix(
+            &NixOptions {
+                query_style: QueryStyle::Params,
+                ..NixOptions::default()
+            },
+            &mut split,
+        )
+        .unwrap();
+        url.into_nix(
+            &NixOptions {
+                query_style: QueryStyle::Template,
+                ..NixOptions::default()
+            },
+            &mut whole,
+        )
+        .unwrap();
+
+        assert!(split.contains("template = \"https://example.com/search\";"));
+        assert!(split.contains("name = \"t\";\n                    value = \"ffab\";"));
+        assert_eq!(
+            whole,
+            r#"        {
+            template = "https://example.com/search?q={searchTerms}&t=ffab";
+            type = "text/html";
+        }
+"#
+        );
+    }
+
+    #[test]
+    fn params_shapes() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}&amp;t=ffab" />
+            </OpenSearchDescription>
+        "#;
+
+        let url = &parse_opensearch(raw).unwrap().urls[0];
+        let nix_with_shape = |params_shape| {
+            let mut nix = String::new();
+            url.into_nix(
+                &NixOptions {
+                    params_shape,
+                    ..NixOptions::default()
+                },
+                &mut nix,
+            )
+            .unwrap();
+            nix
+        };
+
+        assert_eq!(
+            nix_with_shape(ParamsShape::List),
+            r#"        {
+            template = "https://example.com/search";
+            type = "text/html";
+            params = [
+                {
+                    name = "q";
+                    value = "{searchTerms}";
+                }
+                {
+                    name = "t";
+                    value = "ffab";
+                }
+            ];
+        }
+"#
+        );
+        assert_eq!(
+            nix_with_shape(ParamsShape::Attrset),
+            r#"        {
+            template = "https://example.com/search";
+            type = "text/html";
+            params = {
+                q = "{searchTerms}";
+                t = "ffab";
+            };
+        }
+"#
+        );
+    }
+
+    #[test]
+    fn attrset_params_keep_last_value() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}&amp;lang=en&amp;lang=de&amp;a.b=c" />
+            </OpenSearchDescription>
+        "#;
+
+        let url = &parse_opensearch(raw).unwrap().urls[0];
+        let mut nix = String::new();
+        url.into_nix(
+            &NixOptions {
+                params_shape: ParamsShape::Attrset,
+                ..NixOptions::default()
+            },
+            &mut nix,
+        )
+        .unwrap();
+
+        assert!(nix.contains("lang = \"de\";"));
+        assert!(!nix.contains("\"en\""));
+        assert!(nix.contains("\"a.b\" = \"c\";"));
+        assert_eq!(url.repeated_param_names(), ["lang"]);
+    }
+
+    #[test]
+    fn attrset_params_quote_keywords() {
+        let raw = r#"<OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}&amp;if=1&amp;in=2&amp;or=3&amp;inherit=4&amp;info=5" />
+            </OpenSearchDescription>"#;
+
+        let nix = parse_opensearch(raw)
+            .unwrap()
+            .to_nix_with(&NixOptions {
+                params_shape: ParamsShape::Attrset,
+                ..NixOptions::default()
+            })
+            .unwrap();
+
+        for keyword in ["if", "in", "or", "inherit"] {
+            assert!(nix.contains(&format!("\"{}\" = ", keyword)));
+        }
+        assert!(nix.contains(" info = \"5\";"));
+        assert!(rnix::Root::parse(&format!("{{ {} }}", nix)).ok().is_ok());
+    }
+
+    #[test]
+    fn all_icons_comment() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Image height="16" width="16" type="image/png">https://example.com/small.png</Image>
+                <Image height="64" width="64" type="image/png">https://example.com/large.png</Image>
+                <Image height="32" width="32" type="image/x-icon">https://example.com/icon.ico</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let mut nix = String::new();
+        parsed
+            .into_nix(
+                &NixOptions {
+                    all_icons: true,
+                    ..NixOptions::default()
+                },
+                &mut nix,
+            )
+            .unwrap();
+
+        assert!(nix.contains(
+            r#"    iconUpdateURL = "https://example.com/large.png";
+    # Alternative icons:
+    #   https://example.com/small.png (image/png, 16x16)
+    #   https://example.com/icon.ico (image/x-icon, 32x32)
+"#
+        ));
+        assert!(rnix::Root::parse(&format!("{{ {} }}", nix)).ok().is_ok());
+    }
+
+    #[test]
+    fn data_uri_image() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Image height="16" width="16" type="image/png">data:image/png;base64,iVBORw0KGgo=</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let nix = parse_opensearch(raw).unwrap().to_nix().unwrap();
+
+        assert!(nix.contains("    icon = \"data:image/png;base64,iVBORw0KGgo=\";\n"));
+        assert!(!nix.contains("iconUpdateURL"));
+    }
+
+    #[test]
+    fn self_url_excluded() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <Url type="application/x-suggestions+json" rel="suggestions" template="https://example.com/json?q={searchTerms}" />
+                <Url type="application/opensearchdescription+xml" rel="self" template="https://example.com/opensearch.xml" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+
+        assert_eq!(parsed.urls[0].rel, OpenSearchRel::Results);
+        assert_eq!(parsed.urls[1].rel, OpenSearchRel::Suggestions);
+        assert_eq!(parsed.urls[2].rel, OpenSearchRel::SelfLink);
+
+        let mut nix = String::new();
+        parsed.into_nix(&NixOptions::default(), &mut nix).unwrap();
+
+        assert!(nix.contains("rel = \"suggestions\";"));
+        assert!(!nix.contains("opensearch.xml"));
+        assert!(!nix.contains("rel = \"self\";"));
+    }
+}
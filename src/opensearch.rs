@@ -0,0 +1,2115 @@
+//! The OpenSearch description document model and its xml deserialization.
+
+use std::{cell::OnceCell, collections::BTreeMap, fmt::Display, str::FromStr};
+
+use mime::Mime;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_with::{rust::deserialize_ignore_any, serde_as, DisplayFromStr};
+use url::Url;
+
+use crate::Error;
+
+/// The end of an optional `{placeholder?}`.
+const OPTIONAL_CLOSE: &str = "?}";
+/// The percent-encoded `?` of an optional placeholder.
+const ENCODED_OPTIONAL: &str = "%3F";
+
+/// The most characters the OpenSearch spec allows in a `<ShortName>`.
+pub const MAX_SHORT_NAME_LENGTH: usize = 16;
+
+/// The url schemes templates may use without being explicitly allowed.
+const SUPPORTED_SCHEMES: &[&str] = &["http", "https"];
+
+/// The placeholders Firefox fills in when it runs a search; any others are sent literally.
+pub const SUBSTITUTED_PLACEHOLDERS: &[&str] = &["searchTerms", "startIndex", "startPage", "count"];
+
+/// What `{startIndex}` and `{startPage}` count from unless a `<Url>` says otherwise.
+pub const DEFAULT_OFFSET: u32 = 1;
+
+const FALLBACK_FAVICON_PATH: &str = "/favicon.ico";
+const FALLBACK_FAVICON_TYPE: &str = "image/x-icon";
+
+/// The image types implied by common file extensions, for images that don't declare one.
+const IMAGE_EXTENSION_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("ico", "image/x-icon"),
+    ("svg", "image/svg+xml"),
+    ("gif", "image/gif"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("webp", "image/webp"),
+];
+
+/// The `<Query>` role of search terms expected to return results.
+const EXAMPLE_QUERY_ROLE: &str = "example";
+
+/// Url types that return a feed of results rather than a webpage or suggestions.
+const FEED_TYPES: &[&str] = &["application/atom+xml", "application/rss+xml"];
+
+/// Icon MIME types from most to least preferred; unlisted types rank below all of these.
+const ICON_MIME_PREFERENCE: &[&str] = &["image/png", "image/x-icon", "image/svg+xml"];
+
+/// A parsed OpenSearch description document. It serializes as-is, field for field; see
+/// [`EngineOutput`](crate::EngineOutput) for a shape closer to what browsers consume.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(try_from = "OpenSearchDescriptionXml")]
+pub struct OpenSearchDescription {
+    pub short_name: String,
+    pub description: String,
+    pub images: Vec<OpenSearchImage>,
+    pub urls: Vec<OpenSearchUrl>,
+    pub queries: Vec<OpenSearchQuery>,
+    /// The languages results are available in, such as `en-us` or `*` for any.
+    pub languages: Vec<String>,
+    /// The character encodings queries may be sent in.
+    pub input_encodings: Vec<String>,
+    /// The character encodings results may be returned in.
+    pub output_encodings: Vec<String>,
+    /// The page holding the site's own search form, from Mozilla's `<moz:SearchForm>` extension.
+    pub search_form: Option<Url>,
+    /// Problems in the document that were tolerated while parsing it.
+    pub warnings: Vec<String>,
+    /// The namespaces the root element declares by prefix, such as `moz`, with any default
+    /// namespace under an empty prefix. Only the `parse_opensearch` functions fill this in.
+    pub namespaces: BTreeMap<String, String>,
+}
+
+/// The child elements of `<OpenSearchDescription>`. Element names are case-sensitive, so the
+/// all-lowercase and all-uppercase spellings some descriptors use are accepted as aliases.
+#[derive(Debug, Deserialize)]
+enum OpenSearchDescriptionXmlValue {
+    #[serde(alias = "shortname", alias = "SHORTNAME")]
+    ShortName(String),
+    #[serde(alias = "description", alias = "DESCRIPTION")]
+    Description(String),
+    #[serde(alias = "image", alias = "IMAGE")]
+    Image(OpenSearchImageXml),
+    #[serde(alias = "url", alias = "URL")]
+    Url(OpenSearchUrlXml),
+    #[serde(alias = "query", alias = "QUERY")]
+    Query(OpenSearchQuery),
+    #[serde(alias = "language", alias = "LANGUAGE")]
+    Language(String),
+    #[serde(alias = "inputencoding", alias = "INPUTENCODING")]
+    InputEncoding(String),
+    #[serde(alias = "outputencoding", alias = "OUTPUTENCODING")]
+    OutputEncoding(String),
+    #[serde(alias = "searchform", alias = "SEARCHFORM")]
+    SearchForm(String),
+
+    #[serde(other, deserialize_with = "deserialize_ignore_any")]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "OpenSearchDescription")]
+pub(crate) struct OpenSearchDescriptionXml {
+    #[serde(rename = "$value")]
+    values: Vec<OpenSearchDescriptionXmlValue>,
+    /// Filled in from the root element after deserializing.
+    #[serde(skip)]
+    pub(crate) namespaces: BTreeMap<String, String>,
+}
+
+impl TryFrom<OpenSearchDescriptionXml> for OpenSearchDescription {
+    type Error = Error;
+
+    fn try_from(value: OpenSearchDescriptionXml) -> Result<Self, Self::Error> {
+        value.resolve(None, false)
+    }
+}
+
+/// Lowercases a parsed MIME type's essence, so types can be compared as plain strings however
+/// the document capitalized them.
+pub(crate) fn normalize_mime(mime: Mime) -> Mime {
+    let essence = mime.essence_str();
+
+    if !essence.bytes().any(|byte| byte.is_ascii_uppercase()) {
+        return mime;
+    }
+
+    let lowercase = essence.to_ascii_lowercase() + &mime.as_ref()[essence.len()..];
+    lowercase.parse().unwrap_or(mime)
+}
+
+/// Strips CDATA markers left in text, as when a descriptor escapes them so they reach the
+/// parser as characters rather than a CDATA section.
+fn unwrap_cdata(text: String) -> String {
+    match text
+        .trim()
+        .strip_prefix("<![CDATA[")
+        .and_then(|text| text.strip_suffix("]]>"))
+    {
+        Some(content) => content.to_string(),
+        None => text,
+    }
+}
+
+impl OpenSearchDescriptionXml {
+    /// Builds the description, resolving relative image and template urls against `base`. With
+    /// `skip_invalid`, a `<Url>` that can't be understood is left out with a warning instead of
+    /// failing the whole document.
+    pub(crate) fn resolve(
+        self,
+        base: Option<&Url>,
+        skip_invalid: bool,
+    ) -> Result<OpenSearchDescription, Error> {
+        let mut images = Vec::new();
+        let mut urls = Vec::new();
+        let mut queries = Vec::new();
+        let mut languages = Vec::new();
+        let mut input_encodings = Vec::new();
+        let mut output_encodings = Vec::new();
+        let short_name = OnceCell::new();
+        let description = OnceCell::new();
+        let mut search_form = None;
+        let mut warnings = Vec::new();
+
+        for xml_value in self.values {
+            match xml_value {
+                OpenSearchDescriptionXmlValue::Url(url) => match url.resolve(base) {
+                    Ok(url) => urls.push(url),
+                    Err(error) if skip_invalid => warnings.push(format!("skipping {}", error)),
+                    Err(error) => return Err(error),
+                },
+                OpenSearchDescriptionXmlValue::Image(image) => images.push(image.resolve(base)?),
+                OpenSearchDescriptionXmlValue::Query(query) => queries.push(query),
+                OpenSearchDescriptionXmlValue::Language(language) => {
+                    languages.push(language.trim().to_string())
+                }
+                OpenSearchDescriptionXmlValue::InputEncoding(encoding) => {
+                    input_encodings.push(encoding.trim().to_string())
+                }
+                OpenSearchDescriptionXmlValue::OutputEncoding(encoding) => {
+                    output_encodings.push(encoding.trim().to_string())
+                }
+                OpenSearchDescriptionXmlValue::ShortName(provided_name) => {
+                    if let Err(extra) = short_name.set(unwrap_cdata(provided_name)) {
+                        warnings.push(format!("ignoring extra ShortName {:?}", extra));
+                    }
+                }
+                OpenSearchDescriptionXmlValue::Description(provided_description) => {
+                    if let Err(extra) = description.set(unwrap_cdata(provided_description)) {
+                        warnings.push(format!("ignoring extra Description {:?}", extra));
+                    }
+                }
+                OpenSearchDescriptionXmlValue::SearchForm(href) => {
+                    match Url::options().base_url(base).parse(href.trim()) {
+                        Ok(url) if search_form.is_none() => search_form = Some(url),
+                        Ok(_) => warnings.push(format!("ignoring extra SearchForm {:?}", href)),
+                        Err(error) => {
+                            warnings.push(format!("ignoring SearchForm {:?}: {}", href, error))
+                        }
+                    }
+                }
+                OpenSearchDescriptionXmlValue::Other => (),
+            }
+        }
+
+        Ok(OpenSearchDescription {
+            short_name: short_name.into_inner().unwrap_or_default(),
+            description: description.into_inner().unwrap_or_default(),
+            images,
+            urls,
+            queries,
+            languages,
+            input_encodings,
+            output_encodings,
+            search_form,
+            warnings,
+            namespaces: self.namespaces,
+        })
+    }
+}
+
+impl OpenSearchDescription {
+    /// Checks that the description defines at least one url that returns search results.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.urls.iter().any(OpenSearchUrl::is_results) {
+            return Ok(());
+        }
+
+        let found = if self.urls.is_empty() {
+            "no urls".to_string()
+        } else {
+            self.urls
+                .iter()
+                .map(|url| url.template_type.essence_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        Err(Error::NoResultsUrl {
+            short_name: self.short_name.clone(),
+            found,
+        })
+    }
+
+    /// Checks that every emitted url template uses http or https, or one of `extra_schemes`, so
+    /// the engine doesn't hand browsers something like a `javascript:` url.
+    pub fn validate_schemes(&self, extra_schemes: &[String]) -> Result<(), Error> {
+        let unsupported = self
+            .urls
+            .iter()
+            .filter(|url| url.rel != OpenSearchRel::SelfLink)
+            .find(|url| {
+                let scheme = url.template.scheme();
+
+                !SUPPORTED_SCHEMES.contains(&scheme)
+                    && !extra_schemes
+                        .iter()
+                        .any(|extra| extra.eq_ignore_ascii_case(scheme))
+            });
+
+        match unsupported {
+            Some(url) => Err(Error::UnsupportedScheme {
+                template: url.full_template(),
+                scheme: url.template.scheme().to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that the short name is given and fits within the spec's
+    /// [`MAX_SHORT_NAME_LENGTH`].
+    pub fn validate_short_name(&self) -> Result<(), Error> {
+        if self.short_name.trim().is_empty() {
+            return Err(Error::EmptyShortName);
+        }
+
+        let length = self.short_name.chars().count();
+
+        if length > MAX_SHORT_NAME_LENGTH {
+            return Err(Error::ShortNameTooLong {
+                short_name: self.short_name.clone(),
+                length,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Where the description says it can be fetched from, by its `rel="self"` url.
+    pub fn self_url(&self) -> Option<&Url> {
+        self.urls
+            .iter()
+            .find(|url| url.rel == OpenSearchRel::SelfLink)
+            .map(|url| &url.template)
+    }
+
+    /// The search terms of the `<Query role="example">`, for trying the engine out.
+    pub fn example_search_terms(&self) -> Option<&str> {
+        self.queries
+            .iter()
+            .find(|query| query.role == EXAMPLE_QUERY_ROLE)
+            .and_then(|query| query.search_terms.as_deref())
+    }
+
+    /// Picks the icon to give the engine: the most preferred MIME type, then the largest, then
+    /// any type over none, then the type's name, then the first listed.
+    pub fn select_icon(images: &[OpenSearchImage]) -> Option<&OpenSearchImage> {
+        images.iter().min()
+    }
+
+    /// Sorts the results urls first, then by `rel`, type and template, so the same urls always
+    /// come out in the same order however the document lists them.
+    pub fn sort_urls(&mut self) {
+        self.urls.sort_by(|a, b| {
+            b.is_results()
+                .cmp(&a.is_results())
+                .then_with(|| a.rel.cmp(&b.rel))
+                .then_with(|| {
+                    a.template_type
+                        .essence_str()
+                        .cmp(b.template_type.essence_str())
+                })
+                .then_with(|| a.template.as_str().cmp(b.template.as_str()))
+        });
+    }
+
+    /// Keeps only the urls of the given types, compared without parameters such as `charset`.
+    /// Fails if the description has no url of one of the types, as it's likely misspelled.
+    pub fn retain_url_types(&mut self, url_types: &[Mime]) -> Result<(), Error> {
+        let matches = |url: &OpenSearchUrl, url_type: &Mime| {
+            url.template_type.essence_str() == url_type.essence_str()
+        };
+
+        if let Some(missing) = url_types
+            .iter()
+            .find(|url_type| !self.urls.iter().any(|url| matches(url, url_type)))
+        {
+            return Err(Error::UrlTypeNotFound {
+                short_name: self.short_name.clone(),
+                url_type: missing.essence_str().to_string(),
+                found: self
+                    .urls
+                    .iter()
+                    .map(|url| url.template_type.essence_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            });
+        }
+
+        self.urls
+            .retain(|url| url_types.iter().any(|url_type| matches(url, url_type)));
+        Ok(())
+    }
+
+    /// Drops urls that repeat an earlier one up to the scheme and a trailing slash, keeping the
+    /// https version where there's a choice.
+    pub fn dedupe_urls(&mut self) {
+        let mut deduped: Vec<OpenSearchUrl> = Vec::with_capacity(self.urls.len());
+
+        for url in self.urls.drain(..) {
+            let key = url.dedupe_key();
+
+            match deduped.iter_mut().find(|kept| kept.dedupe_key() == key) {
+                Some(kept)
+                    if kept.template.scheme() == "http" && url.template.scheme() == "https" =>
+                {
+                    *kept = url
+                }
+                Some(_) => (),
+                None => deduped.push(url),
+            }
+        }
+
+        self.urls = deduped;
+    }
+
+    /// Falls back to the conventional `/favicon.ico` at the origin of `base` when the description
+    /// has no images of its own.
+    pub fn add_fallback_favicon(&mut self, base: &Url) {
+        if !self.images.is_empty() {
+            return;
+        }
+
+        if let Ok(url) = base.join(FALLBACK_FAVICON_PATH) {
+            self.images.push(OpenSearchImage {
+                image_type: Some(
+                    FALLBACK_FAVICON_TYPE
+                        .parse()
+                        .expect("valid favicon MIME type"),
+                ),
+                width: None,
+                height: None,
+                url,
+            });
+        }
+    }
+
+    /// Names the optional elements the description leaves out that browsers make use of.
+    pub fn missing_recommended_fields(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+
+        if self.description.trim().is_empty() {
+            missing.push("Description");
+        }
+
+        if self.images.is_empty() {
+            missing.push("Image");
+        }
+
+        if !self.urls.iter().any(OpenSearchUrl::is_suggestions) {
+            missing.push("suggestions Url");
+        }
+
+        missing
+    }
+}
+
+/// A `<Query>` element, such as the `example` query tools can use to test the engine.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenSearchQuery {
+    pub role: String,
+    pub search_terms: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpenSearchMethod {
+    #[default]
+    Get,
+    Post,
+}
+
+impl FromStr for OpenSearchMethod {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("get") {
+            Ok(Self::Get)
+        } else if value.eq_ignore_ascii_case("post") {
+            Ok(Self::Post)
+        } else {
+            Err(format!("Unsupported OpenSearch url method: {}", value))
+        }
+    }
+}
+
+impl Display for OpenSearchMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Get => f.write_str("GET"),
+            Self::Post => f.write_str("POST"),
+        }
+    }
+}
+
+/// The role of a `<Url>` element, as given by its `rel` attribute, ordered by importance.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OpenSearchRel {
+    #[default]
+    Results,
+    Suggestions,
+    /// A link back to the description document itself.
+    SelfLink,
+    Collection,
+    Other(String),
+}
+
+impl FromStr for OpenSearchRel {
+    type Err = std::convert::Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "" | "results" => Self::Results,
+            "suggestions" => Self::Suggestions,
+            "self" => Self::SelfLink,
+            "collection" => Self::Collection,
+            _ => Self::Other(value.to_string()),
+        })
+    }
+}
+
+impl Display for OpenSearchRel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Results => f.write_str("results"),
+            Self::Suggestions => f.write_str("suggestions"),
+            Self::SelfLink => f.write_str("self"),
+            Self::Collection => f.write_str("collection"),
+            Self::Other(rel) => f.write_str(rel),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct OpenSearchParam {
+    pub name: String,
+    pub value: String,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Clone)]
+pub struct OpenSearchUrl {
+    #[serde_as(as = "DisplayFromStr")]
+    #[serde(rename = "type")]
+    pub template_type: Mime,
+    pub template: Url,
+    #[serde_as(as = "DisplayFromStr")]
+    pub method: OpenSearchMethod,
+    #[serde_as(as = "DisplayFromStr")]
+    pub rel: OpenSearchRel,
+    /// The index of the first result, which `{startIndex}` counts from.
+    pub index_offset: u32,
+    /// The number of the first page of results, which `{startPage}` counts from.
+    pub page_offset: u32,
+    pub params: Vec<OpenSearchParam>,
+}
+
+/// A `<Url>` element whose template may still be relative to the description document.
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct OpenSearchUrlXml {
+    #[serde(rename = "type")]
+    template_type: String,
+    template: String,
+    #[serde_as(as = "DisplayFromStr")]
+    #[serde(default)]
+    method: OpenSearchMethod,
+    #[serde_as(as = "DisplayFromStr")]
+    #[serde(default)]
+    rel: OpenSearchRel,
+    #[serde(rename = "indexOffset", default = "default_offset")]
+    index_offset: u32,
+    #[serde(rename = "pageOffset", default = "default_offset")]
+    page_offset: u32,
+    #[serde(
+        rename = "$value",
+        default,
+        deserialize_with = "deserialize_url_params"
+    )]
+    params: Vec<OpenSearchParam>,
+}
+
+impl OpenSearchUrlXml {
+    fn resolve(self, base: Option<&Url>) -> Result<OpenSearchUrl, Error> {
+        let template_type = self
+            .template_type
+            .trim()
+            .parse()
+            .map(normalize_mime)
+            .map_err(|source| Error::InvalidUrlType {
+                template: self.template.clone(),
+                template_type: self.template_type.clone(),
+                source,
+            })?;
+        let template = Url::options()
+            .base_url(base)
+            .parse(&escape_optional_placeholders(self.template.trim()))
+            .map_err(|source| Error::InvalidTemplateUrl {
+                template: self.template.clone(),
+                source,
+            })?;
+
+        Ok(OpenSearchUrl {
+            template_type,
+            template,
+            method: self.method,
+            rel: self.rel,
+            index_offset: self.index_offset,
+            page_offset: self.page_offset,
+            params: self.params,
+        })
+    }
+}
+
+fn default_offset() -> u32 {
+    DEFAULT_OFFSET
+}
+
+/// Percent-encodes the `?` marking optional `{placeholder?}`s, so one within a path isn't taken as
+/// the start of the query. Query values decode it again and [`restore_placeholders`] restores it.
+fn escape_optional_placeholders(template: &str) -> String {
+    let mut escaped = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        escaped += &rest[..=start];
+        rest = &rest[start + 1..];
+
+        let name_end = rest
+            .find(|character: char| !is_placeholder_name_character(character))
+            .unwrap_or(rest.len());
+
+        escaped += &rest[..name_end];
+        rest = &rest[name_end..];
+
+        if name_end > 0 && rest.starts_with(OPTIONAL_CLOSE) {
+            escaped += ENCODED_OPTIONAL;
+            rest = &rest['?'.len_utf8()..];
+        }
+    }
+
+    escaped += rest;
+    escaped
+}
+
+/// Undoes the percent-encoding [`Url`] applies to `{placeholder}` braces within a path, and to the
+/// `?` of optional placeholders anywhere in the template.
+fn restore_placeholders(template: &str) -> String {
+    const OPEN: &str = "%7B";
+    const CLOSE: &str = "%7D";
+
+    let mut restored = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = [rest.find('{'), find_ignore_ascii_case(rest, OPEN)]
+        .into_iter()
+        .flatten()
+        .min()
+    {
+        restored += &rest[..start];
+        rest = &rest[start..];
+
+        let starts_with = |offset: usize, expected: &str| {
+            rest[offset..]
+                .get(..expected.len())
+                .is_some_and(|found| found.eq_ignore_ascii_case(expected))
+        };
+        let (open, close) = if rest.starts_with('{') {
+            ("{", "}")
+        } else {
+            (OPEN, CLOSE)
+        };
+
+        let name_end = rest[open.len()..]
+            .find(|character: char| !is_placeholder_character(character))
+            .map(|end| end + open.len())
+            .unwrap_or(rest.len());
+        let name = &rest[open.len()..name_end];
+        let optional = starts_with(name_end, ENCODED_OPTIONAL);
+        let close_start = if optional {
+            name_end + ENCODED_OPTIONAL.len()
+        } else {
+            name_end
+        };
+
+        if !name.is_empty() && starts_with(close_start, close) {
+            restored.push('{');
+            restored += name;
+
+            if optional {
+                restored.push('?');
+            }
+
+            restored.push('}');
+            rest = &rest[close_start + close.len()..];
+        } else {
+            restored += open;
+            rest = &rest[open.len()..];
+        }
+    }
+
+    restored += rest;
+    restored
+}
+
+fn find_ignore_ascii_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+fn is_placeholder_character(character: char) -> bool {
+    is_placeholder_name_character(character) || character == '?'
+}
+
+/// The names of the `{placeholder}`s in `text`, without any optional `?` marker.
+fn placeholder_names(text: &str) -> impl Iterator<Item = &str> {
+    text.split('{').skip(1).filter_map(|rest| {
+        let name = rest.split_once('}')?.0;
+        let name = name.strip_suffix('?').unwrap_or(name);
+
+        (!name.is_empty() && name.chars().all(is_placeholder_name_character)).then_some(name)
+    })
+}
+
+/// Replaces each `{placeholder}` in `text`, optional or not, with what `value` gives for its
+/// name.
+fn fill_placeholders(text: &str, value: impl Fn(&str) -> String) -> String {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('{') {
+        filled += &rest[..start];
+        rest = &rest[start..];
+
+        let placeholder = rest[1..].split_once('}').and_then(|(name, _)| {
+            let bare = name.strip_suffix('?').unwrap_or(name);
+            (!bare.is_empty() && bare.chars().all(is_placeholder_name_character))
+                .then_some((bare, name.len() + 2))
+        });
+
+        match placeholder {
+            Some((name, length)) => {
+                filled += &value(name);
+                rest = &rest[length..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    filled += rest;
+    filled
+}
+
+fn is_placeholder_name_character(character: char) -> bool {
+    character.is_ascii_alphanumeric() || matches!(character, '_' | ':' | '.' | '-')
+}
+
+#[derive(Debug, Deserialize)]
+enum OpenSearchUrlXmlValue {
+    #[serde(alias = "param", alias = "PARAM")]
+    Param(OpenSearchParam),
+
+    #[serde(other, deserialize_with = "deserialize_ignore_any")]
+    Other,
+}
+
+fn deserialize_url_params<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<OpenSearchParam>, D::Error> {
+    let values = Vec::<OpenSearchUrlXmlValue>::deserialize(deserializer)?;
+
+    Ok(values
+        .into_iter()
+        .filter_map(|value| match value {
+            OpenSearchUrlXmlValue::Param(param) => Some(param),
+            OpenSearchUrlXmlValue::Other => None,
+        })
+        .collect())
+}
+
+impl OpenSearchUrl {
+    /// Whether the url returns search suggestions, by its `rel` or its conventional type.
+    pub fn is_suggestions(&self) -> bool {
+        self.rel == OpenSearchRel::Suggestions
+            || self
+                .template_type
+                .subtype()
+                .as_str()
+                .starts_with("x-suggestions")
+    }
+
+    /// Whether the url returns search results rather than suggestions or metadata.
+    pub fn is_results(&self) -> bool {
+        self.rel == OpenSearchRel::Results && !self.is_suggestions()
+    }
+
+    /// Whether the url returns an Atom or RSS feed, which browsers can't show as results.
+    pub fn is_feed(&self) -> bool {
+        FEED_TYPES.contains(&self.template_type.essence_str())
+    }
+
+    /// The names of any placeholders in the template or params that Firefox won't substitute,
+    /// such as `{language}`, in the order they first appear.
+    pub fn unsubstituted_placeholders(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        let texts = std::iter::once(self.full_template())
+            .chain(self.params.iter().map(|param| param.value.clone()));
+
+        for text in texts {
+            for name in placeholder_names(&text) {
+                if !SUBSTITUTED_PLACEHOLDERS.contains(&name) && !names.iter().any(|n| n == name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        names
+    }
+
+    /// Whether the template or params use the `{name}` placeholder, optional or not.
+    pub fn uses_placeholder(&self, name: &str) -> bool {
+        std::iter::once(self.full_template())
+            .chain(self.params.iter().map(|param| param.value.clone()))
+            .any(|text| placeholder_names(&text).any(|found| found == name))
+    }
+
+    /// Replaces the `{name}` placeholder, optional or not, with a fixed `value` throughout the
+    /// template and params.
+    pub fn substitute_placeholder(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let substitute = |text: &str| {
+            text.replace(&format!("{{{}}}", name), value)
+                .replace(&format!("{{{}?}}", name), value)
+        };
+
+        let full_template = self.full_template();
+        let template = substitute(&full_template);
+
+        if template != full_template {
+            self.template = Url::parse(&escape_optional_placeholders(&template))
+                .map_err(|source| Error::InvalidTemplateUrl { template, source })?;
+        }
+
+        for param in &mut self.params {
+            param.value = substitute(&param.value);
+        }
+
+        Ok(())
+    }
+
+    /// What two urls must share to be duplicates: everything but the template's scheme and any
+    /// trailing slash on its path.
+    fn dedupe_key(
+        &self,
+    ) -> (
+        &str,
+        &OpenSearchRel,
+        OpenSearchMethod,
+        String,
+        &[OpenSearchParam],
+    ) {
+        let mut template = self.template.clone();
+        let path = template.path().trim_end_matches('/').to_string();
+        template.set_path(&path);
+
+        let template = restore_placeholders(template.as_str());
+        let schemeless = template
+            .split_once("://")
+            .map_or(template.as_str(), |(_, rest)| rest)
+            .to_string();
+
+        (
+            self.template_type.essence_str(),
+            &self.rel,
+            self.method,
+            schemeless,
+            &self.params,
+        )
+    }
+
+    /// Whether `{startIndex}` and `{startPage}` both count from the spec's default of 1.
+    pub fn has_default_offsets(&self) -> bool {
+        self.index_offset == DEFAULT_OFFSET && self.page_offset == DEFAULT_OFFSET
+    }
+
+    /// The template without its query string, keeping any `{placeholder}`s literal.
+    pub fn queryless_template(&self) -> String {
+        let mut queryless_template = self.template.clone();
+        queryless_template.set_query(None);
+
+        restore_placeholders(queryless_template.as_str())
+    }
+
+    /// The whole template including its query string, keeping any `{placeholder}`s literal.
+    pub fn full_template(&self) -> String {
+        restore_placeholders(self.template.as_str())
+    }
+
+    /// Like [`Self::all_params`], but keeps the template's query parameters percent-encoded as
+    /// they were written.
+    pub fn raw_params(&self) -> Vec<OpenSearchParam> {
+        self.template
+            .query()
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+                OpenSearchParam {
+                    name: restore_placeholders(name),
+                    value: restore_placeholders(value),
+                }
+            })
+            .filter(|param| !self.has_child_param(&param.name))
+            .chain(self.params.iter().cloned())
+            .collect()
+    }
+
+    /// Every parameter sent with the query, from the template's query string followed by any
+    /// nested `<Param>` elements. A `<Param>` replaces a query parameter of the same name.
+    pub fn all_params(&self) -> Vec<OpenSearchParam> {
+        self.template
+            .query_pairs()
+            .map(|(name, value)| OpenSearchParam {
+                name: name.into_owned(),
+                value: value.into_owned(),
+            })
+            .filter(|param| !self.has_child_param(&param.name))
+            .chain(self.params.iter().cloned())
+            .collect()
+    }
+
+    /// The url a query for `search_terms` requests, with the first page or result for the
+    /// offsets and any other placeholder left empty. Nested `<Param>`s are added to the query
+    /// string, so this is only what a `GET` url requests.
+    pub fn query_url(&self, search_terms: &str) -> Result<Url, Error> {
+        let fill = |text: &str, encode: fn(&str) -> String| {
+            fill_placeholders(text, |name| match name {
+                "searchTerms" => encode(search_terms),
+                "startIndex" => self.index_offset.to_string(),
+                "startPage" => self.page_offset.to_string(),
+                _ => String::new(),
+            })
+        };
+
+        let template = fill(&self.queryless_template(), |terms| {
+            url::form_urlencoded::byte_serialize(terms.as_bytes())
+                .collect::<String>()
+                .replace('+', "%20")
+        });
+        let mut url = Url::parse(&template)
+            .map_err(|source| Error::InvalidTemplateUrl { template, source })?;
+        let params = self
+            .all_params()
+            .into_iter()
+            .map(|param| (param.name, fill(&param.value, str::to_string)))
+            .collect::<Vec<_>>();
+
+        if !params.is_empty() {
+            url.query_pairs_mut().extend_pairs(params);
+        }
+
+        Ok(url)
+    }
+
+    /// Whether a nested `<Param>` element has the `name`.
+    fn has_child_param(&self, name: &str) -> bool {
+        self.params.iter().any(|param| param.name == name)
+    }
+
+    /// The names given to more than one param, in the order they first repeat.
+    pub fn repeated_param_names(&self) -> Vec<String> {
+        let mut seen = Vec::new();
+        let mut repeated = Vec::new();
+
+        for param in self.all_params() {
+            if seen.contains(&param.name) {
+                if !repeated.contains(&param.name) {
+                    repeated.push(param.name);
+                }
+            } else {
+                seen.push(param.name);
+            }
+        }
+
+        repeated
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Clone, Eq, PartialEq)]
+pub struct OpenSearchImage {
+    /// The declared type, or else one implied by the url, if either says.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(rename = "type")]
+    pub image_type: Option<Mime>,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    pub url: Url,
+}
+
+/// An `<Image>` element whose url may still be relative to the description document.
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct OpenSearchImageXml {
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(rename = "type", default)]
+    image_type: Option<Mime>,
+    width: Option<u16>,
+    height: Option<u16>,
+    #[serde(rename = "$value")]
+    href: String,
+}
+
+impl OpenSearchImageXml {
+    fn resolve(self, base: Option<&Url>) -> Result<OpenSearchImage, Error> {
+        let href = self.href.trim();
+        let url = Url::options()
+            .base_url(base)
+            .parse(href)
+            .map_err(|source| Error::InvalidImageUrl {
+                href: href.to_string(),
+                source,
+            })?;
+
+        Ok(OpenSearchImage {
+            image_type: self
+                .image_type
+                .map(normalize_mime)
+                .or_else(|| infer_image_type(&url)),
+            width: self.width,
+            height: self.height,
+            url,
+        })
+    }
+}
+
+/// The type a typeless image's url implies, by the type of a `data:` uri or the extension of
+/// its path.
+fn infer_image_type(url: &Url) -> Option<Mime> {
+    if url.scheme() == "data" {
+        let media_type = url.path().split([';', ',']).next()?;
+        return media_type.parse().ok().map(normalize_mime);
+    }
+
+    let extension = url.path().rsplit_once('.')?.1;
+
+    IMAGE_EXTENSION_TYPES
+        .iter()
+        .find(|(known, _)| extension.eq_ignore_ascii_case(known))
+        .and_then(|(_, image_type)| image_type.parse().ok())
+}
+
+impl OpenSearchImage {
+    /// The pixel area of the image, widened so large icons can't overflow.
+    pub fn area(&self) -> u32 {
+        u32::from(self.width.unwrap_or_default()) * u32::from(self.height.unwrap_or_default())
+    }
+
+    /// Whether the image is embedded in the descriptor rather than hosted elsewhere.
+    pub fn is_data_uri(&self) -> bool {
+        self.url.scheme() == "data"
+    }
+
+    /// The essence of the image's type, empty if it has none.
+    fn type_name(&self) -> &str {
+        self.image_type.as_ref().map_or("", Mime::essence_str)
+    }
+
+    /// Where the image's type ranks in [`ICON_MIME_PREFERENCE`].
+    fn mime_preference(&self) -> usize {
+        ICON_MIME_PREFERENCE
+            .iter()
+            .position(|mime| self.type_name() == *mime)
+            .unwrap_or(ICON_MIME_PREFERENCE.len())
+    }
+}
+
+impl Ord for OpenSearchImage {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // Unlisted types rank alike, so they're ordered by name, after any with a type, then by url,
+        // to pick the same icon whatever the order they're listed in.
+        self.mime_preference()
+            .cmp(&other.mime_preference())
+            .then_with(|| other.area().cmp(&self.area()))
+            .then_with(|| self.image_type.is_none().cmp(&other.image_type.is_none()))
+            .then_with(|| self.type_name().cmp(other.type_name()))
+            .then_with(|| self.url.as_str().cmp(other.url.as_str()))
+    }
+}
+
+impl PartialOrd for OpenSearchImage {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_opensearch, parse_opensearch_with_base, NixOptions};
+
+    #[test]
+    fn deserialize_xml() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Image height="16" width ="16" type="image/x-icon">https://example.com/image.ico</Image>
+                <Image height="32" width ="32" type="image/x-icon">https://example.com/image.ico</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <Description>Hi there</Description>
+                <Url type="application/x-suggestions+json" template="https://example.com/json?q={searchTerms}" />
+                <Url type="application/x-suggestions+xml" template="https://example.com/xml?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = serde_xml_rs::from_str::<OpenSearchDescription>(raw).unwrap();
+
+        assert_eq!(parsed.short_name, "Test");
+        assert_eq!(parsed.description, "Hi there");
+        assert_eq!(parsed.images.len(), 2);
+        assert_eq!(parsed.urls.len(), 3);
+    }
+
+    #[test]
+    fn example_queries() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Query role="example" searchTerms="cat pictures" />
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <Query role="request" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+
+        assert_eq!(
+            parsed.queries,
+            [
+                OpenSearchQuery {
+                    role: "example".to_string(),
+                    search_terms: Some("cat pictures".to_string()),
+                },
+                OpenSearchQuery {
+                    role: "request".to_string(),
+                    search_terms: None,
+                },
+            ]
+        );
+        assert_eq!(parsed.urls.len(), 1);
+    }
+
+    #[test]
+    fn example_query_url() {
+        let raw = r#"<OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" indexOffset="0" template="https://example.com/find/{searchTerms}?start={startIndex}&amp;lang={language?}">
+                    <Param name="page" value="{startPage?}" />
+                </Url>
+                <Query role="request" searchTerms="ignored" />
+                <Query role="example" searchTerms="cat &amp; dog" />
+            </OpenSearchDescription>"#;
+
+        let parsed = crate::parse_opensearch(raw).unwrap();
+        let search_terms = parsed.example_search_terms().unwrap();
+
+        assert_eq!(search_terms, "cat & dog");
+        assert_eq!(
+            parsed.urls[0].query_url(search_terms).unwrap().as_str(),
+            "https://example.com/find/cat%20%26%20dog?start=0&lang=&page=1"
+        );
+    }
+
+    #[test]
+    fn count_placeholder_kept() {
+        let raw = r#"<OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}&amp;n={count}&amp;max={count?}" />
+                <Url type="application/rss+xml" template="https://example.com/feed/{count}?q={searchTerms}" />
+            </OpenSearchDescription>"#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let url = &parsed.urls[0];
+        let values = |params: Vec<OpenSearchParam>| {
+            params
+                .into_iter()
+                .map(|param| (param.name, param.value))
+                .collect::<Vec<_>>()
+        };
+        let expected = [
+            ("q".to_string(), "{searchTerms}".to_string()),
+            ("n".to_string(), "{count}".to_string()),
+            ("max".to_string(), "{count?}".to_string()),
+        ];
+
+        assert_eq!(values(url.all_params()), expected);
+        assert_eq!(values(url.raw_params()), expected);
+        assert_eq!(
+            url.full_template(),
+            "https://example.com/search?q={searchTerms}&n={count}&max={count?}"
+        );
+        assert!(url.unsubstituted_placeholders().is_empty());
+        assert!(url.uses_placeholder("count"));
+        assert!(parsed.urls[1].uses_placeholder("count"));
+        assert!(!url.uses_placeholder("startPage"));
+
+        let nix = parsed.to_nix().unwrap();
+        assert!(nix.contains(
+            r#"
+                {
+                    name = "n";
+                    value = "{count}";
+                }
+                {
+                    name = "max";
+                    value = "{count?}";
+                }"#
+        ));
+    }
+
+    #[test]
+    fn post_url_merged_params() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" method="post" template="https://example.com/search?q={searchTerms}&amp;source=template">
+                    <Param name="source" value="opensearch"/>
+                    <Param name="lang" value="en"/>
+                </Url>
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let param = |name: &str, value: &str| OpenSearchParam {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+
+        let merged = [
+            param("q", "{searchTerms}"),
+            param("source", "opensearch"),
+            param("lang", "en"),
+        ];
+        assert_eq!(parsed.urls[0].all_params(), merged);
+        assert_eq!(parsed.urls[0].raw_params(), merged);
+
+        let mut nix = String::new();
+        parsed.urls[0]
+            .into_nix(&NixOptions::default(), &mut nix)
+            .unwrap();
+        assert_eq!(
+            nix,
+            r#"        {
+            template = "https://example.com/search";
+            type = "text/html";
+            method = "POST";
+            params = [
+                {
+                    name = "q";
+                    value = "{searchTerms}";
+                }
+                {
+                    name = "source";
+                    value = "opensearch";
+                }
+                {
+                    name = "lang";
+                    value = "en";
+                }
+            ];
+        }
+"#
+        );
+    }
+
+    #[test]
+    fn post_url_params() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" method="POST" template="https://example.com/search">
+                    <Param name="q" value="{searchTerms}"/>
+                    <Param name="source" value="opensearch"/>
+                </Url>
+                <Url type="application/x-suggestions+json" template="https://example.com/json?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let post_url = &parsed.urls[0];
+        let get_url = &parsed.urls[1];
+
+        assert_eq!(post_url.method, OpenSearchMethod::Post);
+        assert_eq!(
+            post_url.all_params(),
+            [
+                OpenSearchParam {
+                    name: "q".to_string(),
+                    value: "{searchTerms}".to_string(),
+                },
+                OpenSearchParam {
+                    name: "source".to_string(),
+                    value: "opensearch".to_string(),
+                },
+            ]
+        );
+        assert_eq!(get_url.method, OpenSearchMethod::Get);
+        assert_eq!(get_url.params, []);
+        assert_eq!(get_url.all_params().len(), 1);
+
+        let mut nix = String::new();
+        post_url.into_nix(&NixOptions::default(), &mut nix).unwrap();
+        assert!(nix.contains("method = \"POST\";"));
+        assert!(nix.contains("name = \"source\";\n                    value = \"opensearch\";"));
+    }
+
+    #[test]
+    fn path_placeholders_restored() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search/{searchTerms}/{startPage}?ie={inputEncoding}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+
+        assert_eq!(
+            parsed.urls[0].queryless_template(),
+            "https://example.com/search/{searchTerms}/{startPage}"
+        );
+    }
+
+    #[test]
+    fn optional_placeholders() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search/{language?}?q={searchTerms}&amp;p={startPage?}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+
+        assert_eq!(
+            parsed.urls[0].queryless_template(),
+            "https://example.com/search/{language?}"
+        );
+        assert_eq!(
+            parsed.urls[0].all_params(),
+            [
+                OpenSearchParam {
+                    name: "q".to_string(),
+                    value: "{searchTerms}".to_string(),
+                },
+                OpenSearchParam {
+                    name: "p".to_string(),
+                    value: "{startPage?}".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn typeless_images() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Image width="16" height="16">https://x/f.ico</Image>
+                <Image width="16" height="16">https://x/icon</Image>
+                <Image width="16" height="16">data:image/png;base64,iVBORw0KGgo=</Image>
+                <Url type="text/html" template="https://x/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let types = parsed
+            .images
+            .iter()
+            .map(|image| image.image_type.as_ref().map(Mime::essence_str))
+            .collect::<Vec<_>>();
+
+        assert_eq!(types, [Some("image/x-icon"), None, Some("image/png")]);
+        assert!(parsed.to_nix().is_ok());
+    }
+
+    #[test]
+    fn element_name_case() {
+        let raw = r#"<?xml version="1.0"?>
+            <opensearchdescription>
+                <shortname>Lower</shortname>
+                <description>Lowercase elements</description>
+                <image type="image/png" width="16" height="16">https://example.com/icon.png</image>
+                <URL type="text/html" template="https://example.com/search">
+                    <param name="q" value="{searchTerms}" />
+                </URL>
+                <INPUTENCODING>UTF-8</INPUTENCODING>
+            </opensearchdescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+
+        assert_eq!(parsed.short_name, "Lower");
+        assert_eq!(parsed.description, "Lowercase elements");
+        assert_eq!(parsed.images.len(), 1);
+        assert_eq!(parsed.urls.len(), 1);
+        assert_eq!(parsed.urls[0].params.len(), 1);
+        assert_eq!(parsed.input_encodings, ["UTF-8"]);
+    }
+
+    #[test]
+    fn url_type_filter() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <Url type="application/x-suggestions+json" template="https://example.com/json?q={searchTerms}" />
+                <Url type="application/x-suggestions+xml" template="https://example.com/xml?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let mut parsed = parse_opensearch(raw).unwrap();
+        parsed
+            .retain_url_types(&["text/html".parse().unwrap()])
+            .unwrap();
+
+        let nix = parsed.to_nix().unwrap();
+        assert_eq!(nix.matches("template = ").count(), 1);
+        assert!(nix.contains("type = \"text/html\";"));
+
+        let error = parse_opensearch(raw)
+            .unwrap()
+            .retain_url_types(&["text/htm".parse().unwrap()])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "\"Test\" has no url of type text/htm (found: text/html, application/x-suggestions+json, application/x-suggestions+xml)"
+        );
+    }
+
+    #[test]
+    fn duplicate_urls() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="http://example.com/search?q={searchTerms}" />
+                <Url type="text/html" template="https://example.com/search/?q={searchTerms}" />
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <Url type="text/html" method="post" template="https://example.com/search?q={searchTerms}" />
+                <Url type="application/rss+xml" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let mut parsed = parse_opensearch(raw).unwrap();
+        parsed.dedupe_urls();
+
+        let templates = parsed
+            .urls
+            .iter()
+            .map(|url| {
+                (
+                    url.template_type.essence_str(),
+                    url.method,
+                    url.full_template(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            templates,
+            [
+                (
+                    "text/html",
+                    OpenSearchMethod::Get,
+                    "https://example.com/search/?q={searchTerms}".to_string()
+                ),
+                (
+                    "text/html",
+                    OpenSearchMethod::Post,
+                    "https://example.com/search?q={searchTerms}".to_string()
+                ),
+                (
+                    "application/rss+xml",
+                    OpenSearchMethod::Get,
+                    "https://example.com/search?q={searchTerms}".to_string()
+                ),
+            ]
+        );
+
+        let nix = parsed.to_nix().unwrap();
+        assert_eq!(nix.matches("type = \"text/html\";").count(), 2);
+    }
+
+    #[test]
+    fn locale_placeholders() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/{language}/search?q={searchTerms}&amp;ie={inputEncoding?}&amp;n={count?}">
+                    <Param name="hl" value="{language}" />
+                </Url>
+            </OpenSearchDescription>
+        "#;
+
+        let mut url = parse_opensearch(raw).unwrap().urls.remove(0);
+        assert_eq!(
+            url.unsubstituted_placeholders(),
+            ["language", "inputEncoding"]
+        );
+
+        url.substitute_placeholder("language", "en-US").unwrap();
+        assert_eq!(
+            url.full_template(),
+            "https://example.com/en-US/search?q={searchTerms}&ie={inputEncoding?}&n={count?}"
+        );
+        assert_eq!(url.params[0].value, "en-US");
+        assert_eq!(url.unsubstituted_placeholders(), ["inputEncoding"]);
+    }
+
+    #[test]
+    fn full_template_keeps_query() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/{lang?}/search?q={searchTerms}&amp;p={startPage?}" />
+            </OpenSearchDescription>
+        "#;
+
+        assert_eq!(
+            parse_opensearch(raw).unwrap().urls[0].full_template(),
+            "https://example.com/{lang?}/search?q={searchTerms}&p={startPage?}"
+        );
+    }
+
+    #[test]
+    fn raw_params_stay_encoded() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}&amp;label=hello%20world&amp;p={startPage?}" />
+            </OpenSearchDescription>
+        "#;
+
+        let url = &parse_opensearch(raw).unwrap().urls[0];
+        let param = |name: &str, value: &str| OpenSearchParam {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+
+        assert_eq!(
+            url.raw_params(),
+            [
+                param("q", "{searchTerms}"),
+                param("label", "hello%20world"),
+                param("p", "{startPage?}"),
+            ]
+        );
+        assert_eq!(url.all_params()[1], param("label", "hello world"));
+    }
+
+    #[test]
+    fn encoded_braces_without_placeholder() {
+        assert_eq!(
+            restore_placeholders("https://example.com/%7B%7D/%7b"),
+            "https://example.com/%7B%7D/%7B"
+        );
+        assert_eq!(
+            restore_placeholders("https://example.com/%7bq%7d"),
+            "https://example.com/{q}"
+        );
+    }
+
+    #[test]
+    fn icon_mime_preference() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Image height="64" width="64" type="image/svg+xml">https://example.com/icon.svg</Image>
+                <Image height="16" width="16" type="image/x-icon">https://example.com/small.ico</Image>
+                <Image height="32" width="32" type="image/x-icon">https://example.com/large.ico</Image>
+                <Image height="16" width="16" type="image/png">https://example.com/icon.png</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let mut sorted_images = parsed.images.clone();
+        sorted_images.sort();
+
+        let sorted_urls = sorted_images
+            .iter()
+            .map(|image| image.url.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            sorted_urls,
+            [
+                "https://example.com/icon.png",
+                "https://example.com/large.ico",
+                "https://example.com/small.ico",
+                "https://example.com/icon.svg",
+            ]
+        );
+
+        let mut nix = String::new();
+        parsed.into_nix(&NixOptions::default(), &mut nix).unwrap();
+        assert!(nix.contains("iconUpdateURL = \"https://example.com/icon.png\";"));
+    }
+
+    #[test]
+    fn uppercase_mime_types() {
+        let description = |image_type: &str, url_type: &str| {
+            parse_opensearch(&format!(
+                r#"<OpenSearchDescription>
+                    <ShortName>Test</ShortName>
+                    <Image height="64" width="64" type="image/svg+xml">https://example.com/icon.svg</Image>
+                    <Image height="16" width="16" type="{}">https://example.com/icon.png</Image>
+                    <Url type="{}" template="https://example.com/search?q={{searchTerms}}" />
+                    <Url type="application/x-suggestions+json" template="https://example.com/json?q={{searchTerms}}" />
+                </OpenSearchDescription>"#,
+                image_type, url_type
+            ))
+            .unwrap()
+        };
+
+        assert_eq!(
+            normalize_mime("Image/PNG; name=Icon".parse().unwrap()).as_ref(),
+            "image/png; name=Icon"
+        );
+
+        let lowercase = description("image/png", "text/html");
+        let mut uppercase = description("IMAGE/PNG", "Text/HTML");
+
+        assert_eq!(uppercase.images, lowercase.images);
+        assert_eq!(
+            OpenSearchDescription::select_icon(&uppercase.images)
+                .unwrap()
+                .url
+                .as_str(),
+            "https://example.com/icon.png"
+        );
+        assert_eq!(
+            uppercase.urls[0].template_type.essence_str(),
+            mime::TEXT_HTML.essence_str()
+        );
+
+        uppercase
+            .retain_url_types(&["TEXT/HTML".parse().unwrap()])
+            .unwrap();
+        assert_eq!(uppercase.urls.len(), 1);
+
+        let options = NixOptions {
+            url_types: vec!["text/html".parse().unwrap()],
+            ..NixOptions::default()
+        };
+        assert_eq!(
+            description("IMAGE/PNG", "TEXT/HTML")
+                .to_nix_with(&options)
+                .unwrap(),
+            lowercase.to_nix_with(&options).unwrap()
+        );
+
+        // Types implied by the url rather than declared rank the same too.
+        let inferred = parse_opensearch(
+            r#"<OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Image height="64" width="64">https://example.com/icon.SVG</Image>
+                <Image height="16" width="16">data:IMAGE/PNG;base64,aWNvbg==</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>"#,
+        )
+        .unwrap();
+        assert!(OpenSearchDescription::select_icon(&inferred.images)
+            .unwrap()
+            .is_data_uri());
+    }
+
+    #[test]
+    fn unlisted_icon_mime_ranks_last() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Image height="64" width="64" type="image/gif">https://example.com/icon.gif</Image>
+                <Image height="16" width="16" type="image/svg+xml">https://example.com/icon.svg</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let mut sorted_images = parsed.images.clone();
+        sorted_images.sort();
+
+        assert_eq!(
+            sorted_images[0].url.as_str(),
+            "https://example.com/icon.svg"
+        );
+    }
+
+    #[test]
+    fn namespace_prefix() {
+        let unprefixed = r#"<?xml version="1.0"?>
+            <OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+                <ShortName>Test</ShortName>
+                <Description>Hi there</Description>
+                <Image height="16" width="16" type="image/png">https://example.com/icon.png</Image>
+                <Url type="text/html" method="post" template="https://example.com/search">
+                    <Param name="q" value="{searchTerms}" />
+                </Url>
+            </OpenSearchDescription>
+        "#;
+        let prefixed = r#"<?xml version="1.0"?>
+            <os:OpenSearchDescription xmlns:os="http://a9.com/-/spec/opensearch/1.1/">
+                <os:ShortName>Test</os:ShortName>
+                <os:Description>Hi there</os:Description>
+                <os:Image height="16" width="16" type="image/png">https://example.com/icon.png</os:Image>
+                <os:Url type="text/html" method="post" template="https://example.com/search">
+                    <os:Param name="q" value="{searchTerms}" />
+                </os:Url>
+            </os:OpenSearchDescription>
+        "#;
+
+        let unprefixed = parse_opensearch(unprefixed).unwrap();
+        let prefixed = parse_opensearch(prefixed).unwrap();
+
+        assert_eq!(prefixed.short_name, "Test");
+        assert_eq!(prefixed.urls[0].params, unprefixed.urls[0].params);
+        assert_eq!(prefixed.images, unprefixed.images);
+        assert_eq!(prefixed.to_nix().unwrap(), unprefixed.to_nix().unwrap());
+    }
+
+    #[test]
+    fn url_offsets() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" indexOffset="0" template="https://example.com/search?q={searchTerms}&amp;start={startIndex}" />
+                <Url type="application/rss+xml" pageOffset="2" template="https://example.com/rss?q={searchTerms}&amp;page={startPage}" />
+                <Url type="application/atom+xml" template="https://example.com/atom?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let offsets = parsed
+            .urls
+            .iter()
+            .map(|url| (url.index_offset, url.page_offset, url.has_default_offsets()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(offsets, [(0, 1, false), (1, 2, false), (1, 1, true)]);
+        assert_eq!(parsed.to_output().urls[0].index_offset, 0);
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Description>Hi there</Description>
+                <Image type="image/png" width="16" height="16">https://example.com/icon.png</Image>
+                <Url type="text/html" method="post" template="https://example.com/search">
+                    <Param name="q" value="{searchTerms}" />
+                </Url>
+                <Query role="example" searchTerms="cat" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let json = serde_json::to_value(&parsed).unwrap();
+
+        assert_eq!(json["short_name"], parsed.short_name);
+        assert_eq!(json["description"], parsed.description);
+        assert_eq!(json["images"][0]["type"], "image/png");
+        assert_eq!(json["images"][0]["width"], 16);
+        assert_eq!(json["images"][0]["url"], parsed.images[0].url.as_str());
+        assert_eq!(json["urls"][0]["type"], "text/html");
+        assert_eq!(json["urls"][0]["template"], "https://example.com/search");
+        assert_eq!(json["urls"][0]["method"], "POST");
+        assert_eq!(json["urls"][0]["rel"], "results");
+        assert_eq!(json["urls"][0]["params"][0]["value"], "{searchTerms}");
+        assert_eq!(json["queries"][0]["searchTerms"], "cat");
+    }
+
+    #[test]
+    fn invalid_url_type() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="not a mime" template="https://example.com/broken?q={searchTerms}" />
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let error = parse_opensearch(raw).unwrap_err();
+        assert!(matches!(error, Error::InvalidUrlType { .. }));
+        assert!(error
+            .to_string()
+            .starts_with(r#"<Url template="https://example.com/broken?q={searchTerms}"> has an incorrectly formatted type "not a mime""#));
+
+        let parsed = crate::parse_opensearch_skipping_invalid(raw, None).unwrap();
+        assert_eq!(parsed.urls.len(), 1);
+        assert_eq!(parsed.urls[0].template.path(), "/search");
+        assert_eq!(parsed.warnings.len(), 1);
+        assert!(parsed.to_nix().is_ok());
+    }
+
+    #[test]
+    fn search_form_and_self_url() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription xmlns:moz="http://www.mozilla.org/2006/browser/search/">
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <Url type="application/opensearchdescription+xml" rel="self" template="https://example.com/opensearch.xml" />
+                <moz:SearchForm>/advanced</moz:SearchForm>
+            </OpenSearchDescription>
+        "#;
+
+        let base = Url::parse("https://example.com/opensearch.xml").unwrap();
+        let parsed = parse_opensearch_with_base(raw, &base).unwrap();
+
+        assert_eq!(
+            parsed.search_form.as_ref().map(Url::as_str),
+            Some("https://example.com/advanced")
+        );
+        assert_eq!(
+            parsed.self_url().map(Url::as_str),
+            Some("https://example.com/opensearch.xml")
+        );
+
+        let parsed = parse_opensearch(raw).unwrap();
+        assert_eq!(parsed.search_form, None);
+        assert_eq!(parsed.warnings.len(), 1);
+    }
+
+    #[test]
+    fn duplicate_short_name() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>First</ShortName>
+                <ShortName>Second</ShortName>
+                <Description>One</Description>
+                <Description>Two</Description>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+
+        assert_eq!(parsed.short_name, "First");
+        assert_eq!(parsed.description, "One");
+        assert_eq!(
+            parsed.warnings,
+            [
+                "ignoring extra ShortName \"Second\"",
+                "ignoring extra Description \"Two\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_scheme() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="javascript:alert('{searchTerms}')" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let error = parsed.validate_schemes(&[]).unwrap_err();
+
+        assert!(
+            matches!(error, Error::UnsupportedScheme { ref scheme, .. } if scheme == "javascript")
+        );
+        assert_eq!(
+            error.to_string(),
+            "url template \"javascript:alert('{searchTerms}')\" uses the unsupported scheme \"javascript\""
+        );
+        assert!(matches!(
+            parsed.to_nix(),
+            Err(Error::UnsupportedScheme { .. })
+        ));
+        assert!(parsed.validate_schemes(&["JavaScript".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn long_short_name() {
+        let raw = |short_name: &str| {
+            format!(
+                r#"<?xml version="1.0"?>
+                <OpenSearchDescription>
+                    <ShortName>{}</ShortName>
+                    <Url type="text/html" template="https://example.com/search?q={{searchTerms}}" />
+                </OpenSearchDescription>"#,
+                short_name
+            )
+        };
+
+        let error = parse_opensearch(&raw("Example Search Engine"))
+            .unwrap()
+            .validate_short_name()
+            .unwrap_err();
+
+        assert!(matches!(error, Error::ShortNameTooLong { length: 21, .. }));
+        assert_eq!(
+            error.to_string(),
+            "short name \"Example Search Engine\" is 21 characters; OpenSearch allows at most 16"
+        );
+        assert!(parse_opensearch(&raw("Sixteen is fine!"))
+            .unwrap()
+            .validate_short_name()
+            .is_ok());
+    }
+
+    #[test]
+    fn languages_and_encodings() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Language>en-us</Language>
+                <Language>*</Language>
+                <InputEncoding>UTF-8</InputEncoding>
+                <InputEncoding>ISO-8859-1</InputEncoding>
+                <OutputEncoding>UTF-8</OutputEncoding>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}&amp;ie={inputEncoding}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+
+        assert_eq!(parsed.languages, ["en-us", "*"]);
+        assert_eq!(parsed.input_encodings, ["UTF-8", "ISO-8859-1"]);
+        assert_eq!(parsed.output_encodings, ["UTF-8"]);
+    }
+
+    #[test]
+    fn missing_recommended_fields() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        assert_eq!(
+            parse_opensearch(raw).unwrap().missing_recommended_fields(),
+            ["Description", "Image", "suggestions Url"]
+        );
+    }
+
+    #[test]
+    fn sorted_urls() {
+        let urls = [
+            r#"<Url type="application/x-suggestions+json" template="https://example.com/json?q={searchTerms}" />"#,
+            r#"<Url type="text/html" template="https://example.com/b?q={searchTerms}" />"#,
+            r#"<Url type="application/opensearchdescription+xml" rel="self" template="https://example.com/opensearch.xml" />"#,
+            r#"<Url type="application/rss+xml" template="https://example.com/rss?q={searchTerms}" />"#,
+            r#"<Url type="text/html" template="https://example.com/a?q={searchTerms}" />"#,
+        ];
+        let raw = |urls: &[&str]| {
+            format!(
+                "<OpenSearchDescription><ShortName>Test</ShortName>{}</OpenSearchDescription>",
+                urls.concat()
+            )
+        };
+
+        let mut shuffled = urls;
+        shuffled.reverse();
+        shuffled.swap(0, 2);
+
+        let mut in_order = parse_opensearch(&raw(&urls)).unwrap();
+        let mut out_of_order = parse_opensearch(&raw(&shuffled)).unwrap();
+        in_order.sort_urls();
+        out_of_order.sort_urls();
+
+        assert_eq!(in_order.to_nix().unwrap(), out_of_order.to_nix().unwrap());
+        assert_eq!(
+            in_order
+                .urls
+                .iter()
+                .map(|url| url.template.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "https://example.com/rss?q={searchTerms}",
+                "https://example.com/a?q={searchTerms}",
+                "https://example.com/b?q={searchTerms}",
+                "https://example.com/json?q={searchTerms}",
+                "https://example.com/opensearch.xml",
+            ]
+        );
+    }
+
+    #[test]
+    fn fallback_favicon() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+        let base = Url::parse("https://example.com/path/opensearch.xml").unwrap();
+
+        let mut without_images = parse_opensearch(raw).unwrap();
+        without_images.add_fallback_favicon(&base);
+
+        assert_eq!(without_images.images.len(), 1);
+        assert_eq!(
+            without_images.images[0].url.as_str(),
+            "https://example.com/favicon.ico"
+        );
+        assert_eq!(
+            without_images.images[0].image_type.as_ref().unwrap(),
+            &"image/x-icon"
+        );
+
+        let mut with_images = parse_opensearch(&raw.replace(
+            "<Url",
+            r#"<Image type="image/png">https://example.com/icon.png</Image><Url"#,
+        ))
+        .unwrap();
+        with_images.add_fallback_favicon(&base);
+
+        assert_eq!(with_images.images.len(), 1);
+        assert_eq!(
+            with_images.images[0].url.as_str(),
+            "https://example.com/icon.png"
+        );
+    }
+
+    #[test]
+    fn relative_image_url() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Image height="16" width="16" type="image/png">/favicon.png</Image>
+                <Image height="16" width="16" type="image/png">icon.png</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let base = Url::parse("https://example.com/path/").unwrap();
+        let parsed = parse_opensearch_with_base(raw, &base).unwrap();
+
+        assert_eq!(
+            parsed.images[0].url.as_str(),
+            "https://example.com/favicon.png"
+        );
+        assert_eq!(
+            parsed.images[1].url.as_str(),
+            "https://example.com/path/icon.png"
+        );
+
+        assert!(matches!(
+            parse_opensearch(raw),
+            Err(Error::InvalidImageUrl { href, .. }) if href == "/favicon.png"
+        ));
+    }
+
+    #[test]
+    fn relative_template() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Url type="text/html" template="/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let base = Url::parse("https://example.com").unwrap();
+        let parsed = parse_opensearch_with_base(raw, &base).unwrap();
+
+        assert_eq!(
+            parsed.urls[0].queryless_template(),
+            "https://example.com/search"
+        );
+        assert_eq!(
+            parsed.urls[0].all_params(),
+            [OpenSearchParam {
+                name: "q".to_string(),
+                value: "{searchTerms}".to_string(),
+            }]
+        );
+
+        assert!(matches!(
+            parse_opensearch(raw),
+            Err(Error::InvalidTemplateUrl { template, .. }) if template == "/search?q={searchTerms}"
+        ));
+    }
+
+    #[test]
+    fn select_icon() {
+        let image = |image_type: &str, size: u16, url: &str| OpenSearchImage {
+            image_type: Some(image_type.parse().unwrap()),
+            width: Some(size),
+            height: Some(size),
+            url: Url::parse(url).unwrap(),
+        };
+
+        assert_eq!(OpenSearchDescription::select_icon(&[]), None);
+
+        let images = [
+            image("image/x-icon", 64, "https://example.com/large.ico"),
+            image("image/png", 16, "https://example.com/small.png"),
+            image("image/png", 32, "https://example.com/first.png"),
+            image("image/png", 32, "https://example.com/second.png"),
+        ];
+        assert_eq!(
+            OpenSearchDescription::select_icon(&images).map(|image| image.url.as_str()),
+            Some("https://example.com/first.png")
+        );
+
+        let images = [
+            image("image/gif", 128, "https://example.com/icon.gif"),
+            image("image/svg+xml", 16, "https://example.com/icon.svg"),
+        ];
+        assert_eq!(
+            OpenSearchDescription::select_icon(&images).map(|image| image.url.as_str()),
+            Some("https://example.com/icon.svg")
+        );
+    }
+
+    #[test]
+    fn large_icon_area() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Image height="1024" width="1024" type="image/png">https://example.com/first.png</Image>
+                <Image height="1024" width="1024" type="image/png">https://example.com/second.png</Image>
+                <Image height="512" width="512" type="image/png">https://example.com/small.png</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let parsed = parse_opensearch(raw).unwrap();
+        let mut sorted_images = parsed.images.clone();
+        sorted_images.sort();
+
+        assert_eq!(sorted_images[0].area(), 1024 * 1024);
+        assert_eq!(
+            sorted_images[2].url.as_str(),
+            "https://example.com/small.png"
+        );
+    }
+}
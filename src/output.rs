@@ -0,0 +1,213 @@
+//! A plain, serializable view of a description for formats other than Nix.
+
+use serde::Serialize;
+use url::Url;
+
+use crate::{OpenSearchDescription, OpenSearchMethod, OpenSearchParam, OpenSearchUrl};
+
+/// A search engine as emitted by the json output format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EngineOutput {
+    pub short_name: String,
+    pub description: String,
+    /// The url of the preferred icon, if the description has any.
+    pub icon: Option<Url>,
+    pub urls: Vec<UrlOutput>,
+    pub languages: Vec<String>,
+    pub input_encodings: Vec<String>,
+    pub output_encodings: Vec<String>,
+}
+
+/// A search url with its query string split out into `params`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UrlOutput {
+    pub template: String,
+    #[serde(rename = "type")]
+    pub template_type: String,
+    pub method: String,
+    pub rel: String,
+    pub index_offset: u32,
+    pub page_offset: u32,
+    pub params: Vec<OpenSearchParam>,
+}
+
+impl From<&OpenSearchUrl> for UrlOutput {
+    fn from(url: &OpenSearchUrl) -> Self {
+        Self {
+            template: url.queryless_template(),
+            template_type: url.template_type.to_string(),
+            method: url.method.to_string(),
+            rel: url.rel.to_string(),
+            index_offset: url.index_offset,
+            page_offset: url.page_offset,
+            params: url.all_params(),
+        }
+    }
+}
+
+impl OpenSearchDescription {
+    /// Builds the serializable view used by non-Nix output formats.
+    pub fn to_output(&self) -> EngineOutput {
+        EngineOutput {
+            short_name: self.short_name.clone(),
+            description: self.description.clone(),
+            icon: Self::select_icon(&self.images).map(|image| image.url.clone()),
+            urls: self.urls.iter().map(UrlOutput::from).collect(),
+            languages: self.languages.clone(),
+            input_encodings: self.input_encodings.clone(),
+            output_encodings: self.output_encodings.clone(),
+        }
+    }
+
+    /// Builds an entry for the `SearchEngines.Add` list of a Firefox `policies.json`, aliased by
+    /// the first of `aliases` as policies allow only one.
+    pub fn to_firefox_policy(&self, aliases: &[String]) -> serde_json::Value {
+        let mut policy = serde_json::Map::new();
+        policy.insert("Name".to_string(), self.short_name.clone().into());
+
+        if let Some(alias) = aliases.first() {
+            policy.insert("Alias".to_string(), alias.clone().into());
+        }
+
+        // Browsers open the results, so prefer a url that returns a webpage.
+        let results_url = self
+            .urls
+            .iter()
+            .filter(|url| url.is_results())
+            .min_by_key(|url| url.template_type.essence_str() != mime::TEXT_HTML.essence_str());
+
+        if let Some(url) = results_url {
+            policy.insert("Method".to_string(), url.method.to_string().into());
+
+            match url.method {
+                OpenSearchMethod::Get => {
+                    policy.insert("URLTemplate".to_string(), url.full_template().into());
+                }
+                OpenSearchMethod::Post => {
+                    let post_data = url
+                        .all_params()
+                        .iter()
+                        .map(|param| format!("{}={}", param.name, param.value))
+                        .collect::<Vec<_>>()
+                        .join("&");
+
+                    policy.insert("URLTemplate".to_string(), url.queryless_template().into());
+                    policy.insert("PostData".to_string(), post_data.into());
+                }
+            }
+        }
+
+        if let Some(image) = Self::select_icon(&self.images) {
+            policy.insert("IconURL".to_string(), image.url.as_str().into());
+        }
+
+        if !self.description.is_empty() {
+            policy.insert("Description".to_string(), self.description.clone().into());
+        }
+
+        if let Some(url) = self
+            .urls
+            .iter()
+            .find(|url| url.is_suggestions() && url.method == OpenSearchMethod::Get)
+        {
+            policy.insert("SuggestURLTemplate".to_string(), url.full_template().into());
+        }
+
+        policy.into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parse_opensearch;
+
+    #[test]
+    fn json_output() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Description>Hi there</Description>
+                <InputEncoding>UTF-8</InputEncoding>
+                <Image height="16" width="16" type="image/x-icon">https://example.com/small.ico</Image>
+                <Image height="32" width="32" type="image/x-icon">https://example.com/large.ico</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <Url type="application/x-suggestions+json" rel="suggestions" template="https://example.com/json?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        let output = parse_opensearch(raw).unwrap().to_output();
+
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!({
+                "short_name": "Test",
+                "description": "Hi there",
+                "icon": "https://example.com/large.ico",
+                "urls": [
+                    {
+                        "template": "https://example.com/search",
+                        "type": "text/html",
+                        "method": "GET",
+                        "rel": "results",
+                        "index_offset": 1,
+                        "page_offset": 1,
+                        "params": [{ "name": "q", "value": "{searchTerms}" }],
+                    },
+                    {
+                        "template": "https://example.com/json",
+                        "type": "application/x-suggestions+json",
+                        "method": "GET",
+                        "rel": "suggestions",
+                        "index_offset": 1,
+                        "page_offset": 1,
+                        "params": [{ "name": "q", "value": "{searchTerms}" }],
+                    },
+                ],
+                "languages": [],
+                "input_encodings": ["UTF-8"],
+                "output_encodings": [],
+            })
+        );
+    }
+
+    #[test]
+    fn firefox_policy() {
+        let raw = r#"<?xml version="1.0"?>
+            <OpenSearchDescription>
+                <ShortName>Test</ShortName>
+                <Description>Hi there</Description>
+                <Image height="16" width="16" type="image/x-icon">https://example.com/small.ico</Image>
+                <Image height="32" width="32" type="image/x-icon">https://example.com/large.ico</Image>
+                <Url type="text/html" template="https://example.com/search?q={searchTerms}" />
+                <Url type="application/x-suggestions+json" rel="suggestions" template="https://example.com/json?q={searchTerms}" />
+            </OpenSearchDescription>
+        "#;
+
+        assert_eq!(
+            parse_opensearch(raw)
+                .unwrap()
+                .to_firefox_policy(&["@test".to_string(), "@t".to_string()]),
+            serde_json::json!({
+                "Name": "Test",
+                "Alias": "@test",
+                "Method": "GET",
+                "URLTemplate": "https://example.com/search?q={searchTerms}",
+                "IconURL": "https://example.com/large.ico",
+                "Description": "Hi there",
+                "SuggestURLTemplate": "https://example.com/json?q={searchTerms}",
+            })
+        );
+
+        let post = parse_opensearch(&raw.replace(
+            r#"<Url type="text/html""#,
+            r#"<Url type="text/html" method="post""#,
+        ))
+        .unwrap()
+        .to_firefox_policy(&[]);
+
+        assert!(post.get("Alias").is_none());
+        assert_eq!(post["Method"], "POST");
+        assert_eq!(post["URLTemplate"], "https://example.com/search");
+        assert_eq!(post["PostData"], "q={searchTerms}");
+    }
+}